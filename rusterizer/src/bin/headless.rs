@@ -0,0 +1,171 @@
+//! Renders frames without a window, through the same `setup`/`update` path as the host, and
+//! writes them as PNG or PPM files.
+//!
+//! Usage: headless [--width N] [--height N] [--samples N] [--frames N] [--timestep SECONDS]
+//!                 [--format png|ppm] [--output DIR] [--root DIR]
+
+use std::{
+    fs::{self, File},
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+    process,
+};
+
+use glam::Vec3;
+use shared::{
+    framebuffer::{Framebuffer, MAX_SAMPLES},
+    transform::Transform,
+    State,
+};
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum ImageFormat {
+    Png,
+    Ppm,
+}
+
+struct Options {
+    width: usize,
+    height: usize,
+    /// MSAA samples per pixel.
+    samples: usize,
+    frames: usize,
+    timestep: f32,
+    format: ImageFormat,
+    output: PathBuf,
+    /// Directory the scene's asset paths are relative to, the current directory by default.
+    root: PathBuf,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            width: 600,
+            height: 600,
+            samples: 1,
+            frames: 1,
+            timestep: 1.0 / 60.0,
+            format: ImageFormat::Png,
+            output: PathBuf::from("frames"),
+            root: PathBuf::from("."),
+        }
+    }
+}
+
+fn parse_args() -> Result<Options, String> {
+    let mut options = Options::default();
+    let mut args = std::env::args().skip(1);
+
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or(format!("Missing value for {}", arg));
+        match arg.as_str() {
+            "--width" => options.width = parse(&value()?)?,
+            "--height" => options.height = parse(&value()?)?,
+            "--samples" => options.samples = parse(&value()?)?,
+            "--frames" => options.frames = parse(&value()?)?,
+            "--timestep" => options.timestep = parse(&value()?)?,
+            "--format" => {
+                options.format = match value()?.as_str() {
+                    "png" => ImageFormat::Png,
+                    "ppm" => ImageFormat::Ppm,
+                    format => return Err(format!("Unknown format: {}", format)),
+                }
+            }
+            "--output" => options.output = PathBuf::from(value()?),
+            "--root" => options.root = PathBuf::from(value()?),
+            _ => return Err(format!("Unknown argument: {}", arg)),
+        }
+    }
+
+    if options.width == 0 || options.height == 0 {
+        return Err("Resolution must be at least 1x1".to_string());
+    }
+    if !options.samples.is_power_of_two() || options.samples > MAX_SAMPLES {
+        return Err(format!(
+            "Samples must be a power of two up to {}",
+            MAX_SAMPLES
+        ));
+    }
+    Ok(options)
+}
+
+fn parse<T: std::str::FromStr>(value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("Invalid value: {}", value))
+}
+
+/// Resolves the framebuffer to tightly packed RGB rows.
+fn to_rgb8(framebuffer: &Framebuffer) -> Vec<u8> {
+    framebuffer
+        .resolve()
+        .iter()
+        .flat_map(|&argb| [(argb >> 16) as u8, (argb >> 8) as u8, argb as u8])
+        .collect()
+}
+
+fn write_frame(path: &Path, framebuffer: &Framebuffer, format: ImageFormat) -> std::io::Result<()> {
+    let (width, height) = (framebuffer.width, framebuffer.height);
+    let mut writer = BufWriter::new(File::create(path)?);
+
+    match format {
+        ImageFormat::Png => {
+            let mut encoder = png::Encoder::new(writer, width as u32, height as u32);
+            encoder.set_color(png::ColorType::Rgb);
+            encoder.set_depth(png::BitDepth::Eight);
+            encoder
+                .write_header()?
+                .write_image_data(&to_rgb8(framebuffer))?;
+        }
+        ImageFormat::Ppm => {
+            write!(writer, "P6\n{} {}\n255\n", width, height)?;
+            writer.write_all(&to_rgb8(framebuffer))?;
+        }
+    }
+    Ok(())
+}
+
+fn main() {
+    let options = parse_args().unwrap_or_else(|error| {
+        eprintln!("{}", error);
+        eprintln!(
+            "Usage: headless [--width N] [--height N] [--samples N] [--frames N] [--timestep SECONDS] \
+             [--format png|ppm] [--output DIR] [--root DIR]"
+        );
+        process::exit(1);
+    });
+
+    let output = options.output;
+    fs::create_dir_all(&output).unwrap_or_else(|error| panic!("{}", error));
+
+    let mut shared_state = State::new(options.width, options.height);
+    shared_state.camera.fov = 1.0;
+    shared_state.camera.transform = Transform::from_translation(Vec3::new(0.0, 1.5, 6.0));
+    shared_state.set_samples(options.samples);
+    shared_state.asset_root = options.root;
+
+    rusterizer::setup(&mut shared_state);
+
+    let extension = match options.format {
+        ImageFormat::Png => "png",
+        ImageFormat::Ppm => "ppm",
+    };
+
+    for frame in 0..options.frames {
+        if shared_state.should_clear {
+            shared_state
+                .framebuffer
+                .clear_color(shared_state.clear_color);
+        }
+        shared_state.framebuffer.clear_depth();
+
+        rusterizer::update(&mut shared_state);
+
+        let path = output.join(format!("frame_{:04}.{}", frame, extension));
+        write_frame(&path, &shared_state.framebuffer, options.format)
+            .unwrap_or_else(|error| panic!("Failed to write {:?}: {}", path, error));
+        println!("Wrote {:?}", path);
+
+        shared_state.time_passed += options.timestep;
+    }
+}
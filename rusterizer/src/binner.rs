@@ -0,0 +1,297 @@
+use std::{
+    ops::Range,
+    sync::atomic::{AtomicUsize, Ordering},
+    thread,
+};
+
+use glam::Vec2;
+use shared::framebuffer::Framebuffer;
+
+use crate::{
+    color::ColorFormat,
+    geometry::{RenderState, Triangle, TriangleSetup},
+    shader::{FragmentShader, Varyings},
+};
+
+/// Width and height of a screen tile in pixels.
+pub const TILE_SIZE: usize = 64;
+
+/// Depth and color of one screen tile, copied out of the target and owned by the worker
+/// shading it. Color is left empty for depth-only targets.
+struct Tile<C> {
+    columns: Range<usize>,
+    rows: Range<usize>,
+    samples: usize,
+    depth: Vec<f32>,
+    color: Vec<C>,
+}
+
+impl<C: ColorFormat> Tile<C> {
+    fn load(columns: Range<usize>, rows: Range<usize>, target: &Framebuffer<C>) -> Self {
+        let samples = target.samples;
+        let size = columns.len() * rows.len() * samples;
+        let mut tile = Self {
+            columns,
+            rows,
+            samples,
+            depth: vec![f32::INFINITY; size],
+            color: vec![C::default(); if target.has_color() { size } else { 0 }],
+        };
+        for y in tile.rows.clone() {
+            let (row, source) = tile.row(y, target);
+            tile.depth[row.clone()].copy_from_slice(&target.depth[source.clone()]);
+            if target.has_color() {
+                tile.color[row].copy_from_slice(&target.color[source]);
+            }
+        }
+        tile
+    }
+
+    fn store(&self, target: &mut Framebuffer<C>) {
+        for y in self.rows.clone() {
+            let (row, dest) = self.row(y, target);
+            target.depth[dest.clone()].copy_from_slice(&self.depth[row.clone()]);
+            if target.has_color() {
+                target.color[dest].copy_from_slice(&self.color[row]);
+            }
+        }
+    }
+
+    /// Ranges of the samples of the tile's row `y`, in the tile's buffers and in the target's.
+    /// Both store pixels row by row, so each row is copied at once.
+    fn row(&self, y: usize, target: &Framebuffer<C>) -> (Range<usize>, Range<usize>) {
+        let len = self.columns.len() * self.samples;
+        let row = self.samples(self.columns.start, y).start;
+        let target = target.index(self.columns.start, y) * self.samples;
+        (row..row + len, target..target + len)
+    }
+
+    /// Range of the pixel's samples in the color buffer, empty without color.
+    fn color_samples(&self, x: usize, y: usize) -> Range<usize> {
+        match self.color.is_empty() {
+            true => 0..0,
+            false => self.samples(x, y),
+        }
+    }
+
+    /// Range of the pixel's samples in the tile's buffers.
+    fn samples(&self, x: usize, y: usize) -> Range<usize> {
+        let pixel = (y - self.rows.start) * self.columns.len() + (x - self.columns.start);
+        pixel * self.samples..(pixel + 1) * self.samples
+    }
+
+    fn draw<U, F: FragmentShader<U, C>>(
+        &mut self,
+        setup: &TriangleSetup<F::Varyings>,
+        uniforms: &U,
+        shader: &F,
+    ) {
+        let (columns, rows) = self.clip_to_tile(setup);
+        setup.rasterize(columns, rows, self.samples, |coverage| {
+            let color = self.color_samples(coverage.x, coverage.y);
+            let pixel = self.samples(coverage.x, coverage.y);
+            setup.shade(
+                coverage,
+                uniforms,
+                shader,
+                &mut self.color[color],
+                &mut self.depth[pixel],
+            );
+        });
+    }
+
+    fn draw_depth<V: Varyings>(&mut self, setup: &TriangleSetup<V>) {
+        let (columns, rows) = self.clip_to_tile(setup);
+        setup.rasterize(columns, rows, self.samples, |coverage| {
+            let pixel = self.samples(coverage.x, coverage.y);
+            setup.write_depth(coverage, &mut self.depth[pixel]);
+        });
+    }
+
+    /// Pixel ranges of the triangle inside the tile.
+    fn clip_to_tile<V: Varyings>(&self, setup: &TriangleSetup<V>) -> (Range<usize>, Range<usize>) {
+        let (columns, rows) = setup.pixel_range();
+        (
+            columns.start.max(self.columns.start)..columns.end.min(self.columns.end),
+            rows.start.max(self.rows.start)..rows.end.min(self.rows.end),
+        )
+    }
+}
+
+/// Triangles of a single draw call, sharing uniforms and a fragment shader.
+trait Batch<C>: Sync {
+    fn draw(&self, triangle: usize, tile: &mut Tile<C>);
+}
+
+struct DrawBatch<'a, U, F: FragmentShader<U, C>, C> {
+    uniforms: &'a U,
+    shader: &'a F,
+    triangles: Vec<TriangleSetup<F::Varyings>>,
+}
+
+impl<U: Sync, F: FragmentShader<U, C>, C: ColorFormat> Batch<C> for DrawBatch<'_, U, F, C> {
+    fn draw(&self, triangle: usize, tile: &mut Tile<C>) {
+        tile.draw(&self.triangles[triangle], self.uniforms, self.shader);
+    }
+}
+
+/// Triangles of a draw call that only writes depth.
+struct DepthBatch<V> {
+    triangles: Vec<TriangleSetup<V>>,
+}
+
+impl<V: Varyings, C: ColorFormat> Batch<C> for DepthBatch<V> {
+    fn draw(&self, triangle: usize, tile: &mut Tile<C>) {
+        tile.draw_depth(&self.triangles[triangle]);
+    }
+}
+
+/// Collects clipped triangles into screen tiles and shades the tiles in parallel on flush.
+///
+/// Triangles keep their submission order within a tile, so the result matches drawing the
+/// same triangles one by one with `draw_triangle_clipped`. Targets store color in the format
+/// `C`, which the fragment shaders of every draw call write.
+pub struct Binner<'a, C = u32> {
+    viewport: Vec2,
+    tiles_x: usize,
+    tiles_y: usize,
+    batches: Vec<Box<dyn Batch<C> + 'a>>,
+    /// Batch and triangle index of every triangle touching a tile, in submission order.
+    bins: Vec<Vec<(usize, usize)>>,
+}
+
+impl<'a, C: ColorFormat> Binner<'a, C> {
+    pub fn new(viewport: Vec2) -> Self {
+        let tiles_x = (viewport.x as usize).div_ceil(TILE_SIZE);
+        let tiles_y = (viewport.y as usize).div_ceil(TILE_SIZE);
+        Self {
+            viewport,
+            tiles_x,
+            tiles_y,
+            batches: Vec::new(),
+            bins: (0..tiles_x * tiles_y).map(|_| Vec::new()).collect(),
+        }
+    }
+
+    pub fn viewport(&self) -> Vec2 {
+        self.viewport
+    }
+
+    /// Sets up and culls clipped triangles, then adds them to every tile their bounding box
+    /// touches.
+    pub fn submit<U: Sync, F: FragmentShader<U, C>>(
+        &mut self,
+        render_state: &RenderState,
+        uniforms: &'a U,
+        shader: &'a F,
+        triangles: &[Triangle<F::Varyings>],
+    ) {
+        let triangles = self.bin(render_state, triangles);
+        self.batches.push(Box::new(DrawBatch {
+            uniforms,
+            shader,
+            triangles,
+        }));
+    }
+
+    /// Like `submit`, but the triangles only write depth and are not shaded.
+    pub fn submit_depth<V: Varyings + 'a>(
+        &mut self,
+        render_state: &RenderState,
+        triangles: &[Triangle<V>],
+    ) {
+        let triangles = self.bin(render_state, triangles);
+        self.batches.push(Box::new(DepthBatch { triangles }));
+    }
+
+    /// Sets up the triangles of the next batch and adds them to the tiles they touch.
+    fn bin<V: Varyings>(
+        &mut self,
+        render_state: &RenderState,
+        triangles: &[Triangle<V>],
+    ) -> Vec<TriangleSetup<V>> {
+        let batch_id = self.batches.len();
+        let mut setups = Vec::with_capacity(triangles.len());
+
+        for triangle in triangles {
+            let Some(setup) = TriangleSetup::new(triangle, self.viewport, render_state) else {
+                continue;
+            };
+            let (columns, rows) = setup.pixel_range();
+            if columns.is_empty() || rows.is_empty() {
+                continue;
+            }
+
+            let id = setups.len();
+            let tile_columns = columns.start / TILE_SIZE..=((columns.end - 1) / TILE_SIZE);
+            for tile_y in rows.start / TILE_SIZE..=((rows.end - 1) / TILE_SIZE) {
+                for tile_x in tile_columns.clone() {
+                    self.bins[tile_y * self.tiles_x + tile_x].push((batch_id, id));
+                }
+            }
+            setups.push(setup);
+        }
+        setups
+    }
+
+    fn shade_tile(&self, tile_id: usize, target: &Framebuffer<C>) -> Tile<C> {
+        let x = (tile_id % self.tiles_x) * TILE_SIZE;
+        let y = (tile_id / self.tiles_x) * TILE_SIZE;
+        let mut tile = Tile::load(
+            x..(x + TILE_SIZE).min(self.viewport.x as usize),
+            y..(y + TILE_SIZE).min(self.viewport.y as usize),
+            target,
+        );
+
+        for &(batch, triangle) in &self.bins[tile_id] {
+            self.batches[batch].draw(triangle, &mut tile);
+        }
+        tile
+    }
+
+    /// Shades all binned triangles, one tile at a time per worker thread, and writes the
+    /// shaded tiles back to the target. The target must match the binner's viewport.
+    pub fn flush(self, target: &mut Framebuffer<C>) {
+        assert_eq!(
+            self.viewport,
+            Vec2::new(target.width as f32, target.height as f32),
+            "The target has to match the binner's viewport"
+        );
+
+        let tile_count = self.tiles_x * self.tiles_y;
+        let next_tile = AtomicUsize::new(0);
+        let workers = thread::available_parallelism()
+            .map(|count| count.get())
+            .unwrap_or(1)
+            .min(tile_count);
+
+        let tiles: Vec<Tile<C>> = thread::scope(|scope| {
+            let handles: Vec<_> = (0..workers)
+                .map(|_| {
+                    scope.spawn(|| {
+                        let mut shaded = Vec::new();
+                        loop {
+                            let tile_id = next_tile.fetch_add(1, Ordering::Relaxed);
+                            if tile_id >= tile_count {
+                                break;
+                            }
+                            if !self.bins[tile_id].is_empty() {
+                                shaded.push(self.shade_tile(tile_id, target));
+                            }
+                        }
+                        shaded
+                    })
+                })
+                .collect();
+
+            handles
+                .into_iter()
+                .flat_map(|handle| handle.join().unwrap())
+                .collect()
+        });
+
+        for tile in &tiles {
+            tile.store(target);
+        }
+    }
+}
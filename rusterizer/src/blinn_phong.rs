@@ -0,0 +1,131 @@
+//! Blinn-Phong lighting of vertex colors, to preview geometry before it has a material.
+
+use glam::{Mat3, Vec3, Vec4};
+use shared::{
+    light::Light,
+    mesh::{GltfVertex, Vertex},
+};
+
+use crate::{
+    color::ColorFormat,
+    impl_varyings,
+    shader::{Fragment, FragmentShader, Transforms, VertexOutput, VertexShader},
+};
+
+pub struct BlinnPhongUniforms<'a> {
+    pub transforms: Transforms,
+    pub lights: &'a [Light],
+    pub camera_position: Vec3,
+    /// Light reaching every surface from all directions.
+    pub ambient_light: Vec3,
+    /// Color of the highlights, multiplied with the light.
+    pub specular: Vec3,
+    /// Exponent of the highlights, higher is smaller and sharper.
+    pub shininess: f32,
+}
+
+impl AsRef<Transforms> for BlinnPhongUniforms<'_> {
+    fn as_ref(&self) -> &Transforms {
+        &self.transforms
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct BlinnPhongVaryings {
+    pub world_position: Vec3,
+    pub normal: Vec3,
+    pub color: Vec3,
+}
+
+impl_varyings!(BlinnPhongVaryings {
+    world_position,
+    normal,
+    color
+});
+
+/// Lights the vertex color, as linear color, with ambient, diffuse and specular terms.
+pub struct BlinnPhongShader;
+
+impl BlinnPhongShader {
+    fn varyings(
+        transforms: &Transforms,
+        position: Vec4,
+        normal: Vec3,
+        color: Vec3,
+    ) -> VertexOutput<BlinnPhongVaryings> {
+        let normal_matrix = Mat3::from_mat4(transforms.model).inverse().transpose();
+        VertexOutput {
+            position: transforms.model_view_projection() * position,
+            varyings: BlinnPhongVaryings {
+                world_position: (transforms.model * position).truncate(),
+                normal: normal_matrix * normal,
+                color,
+            },
+        }
+    }
+}
+
+impl VertexShader<BlinnPhongUniforms<'_>> for BlinnPhongShader {
+    type Varyings = BlinnPhongVaryings;
+
+    fn vertex(
+        &self,
+        uniforms: &BlinnPhongUniforms,
+        vertex: &Vertex,
+    ) -> VertexOutput<BlinnPhongVaryings> {
+        Self::varyings(
+            &uniforms.transforms,
+            vertex.position,
+            vertex.normal,
+            vertex.color,
+        )
+    }
+}
+
+impl VertexShader<BlinnPhongUniforms<'_>, GltfVertex> for BlinnPhongShader {
+    type Varyings = BlinnPhongVaryings;
+
+    fn vertex(
+        &self,
+        uniforms: &BlinnPhongUniforms,
+        vertex: &GltfVertex,
+    ) -> VertexOutput<BlinnPhongVaryings> {
+        Self::varyings(
+            &uniforms.transforms,
+            vertex.position,
+            vertex.normal,
+            vertex.color.truncate(),
+        )
+    }
+}
+
+impl<C: ColorFormat> FragmentShader<BlinnPhongUniforms<'_>, C> for BlinnPhongShader {
+    type Varyings = BlinnPhongVaryings;
+
+    fn fragment(
+        &self,
+        uniforms: &BlinnPhongUniforms,
+        fragment: &Fragment<BlinnPhongVaryings>,
+    ) -> Option<C> {
+        let varyings = &fragment.varyings;
+        let mut n = varyings.normal.normalize_or_zero();
+        if !fragment.front_facing {
+            n = -n;
+        }
+        let v = (uniforms.camera_position - varyings.world_position).normalize_or_zero();
+
+        let mut color = uniforms.ambient_light * varyings.color;
+        for light in uniforms.lights {
+            let (l, radiance) = light.illuminate(varyings.world_position);
+            let n_dot_l = n.dot(l);
+            if n_dot_l <= 0.0 {
+                continue;
+            }
+            let h = (v + l).normalize_or_zero();
+            let specular = uniforms.specular * n.dot(h).max(0.0).powf(uniforms.shininess);
+            color += (varyings.color * n_dot_l + specular) * radiance;
+        }
+
+        Some(C::from_linear(color.extend(1.0)))
+    }
+}
@@ -1,5 +1,39 @@
 use std::ops::*;
 
+use glam::Vec4;
+use shared::{linear_to_srgb, srgb_to_linear, texture::Texel};
+
+/// Color formats shaders can write. Shading happens in linear light: 8-bit ARGB targets store
+/// color sRGB encoded, ready to be displayed, and `Vec4` targets store linear RGBA as is. The
+/// channels as stored are the texel's `Texel::to_vec4`.
+pub trait ColorFormat: Texel {
+    /// Converts linear RGBA to the format, sRGB encoding and clamping it for 8-bit color.
+    fn from_linear(rgba: Vec4) -> Self;
+    /// Linear RGBA of the color.
+    fn to_linear(self) -> Vec4;
+}
+
+impl ColorFormat for u32 {
+    fn from_linear(rgba: Vec4) -> Self {
+        Self::from_vec4(linear_to_srgb(rgba.truncate()).extend(rgba.w))
+    }
+
+    fn to_linear(self) -> Vec4 {
+        let rgba = self.to_vec4();
+        srgb_to_linear(rgba.truncate()).extend(rgba.w)
+    }
+}
+
+impl ColorFormat for Vec4 {
+    fn from_linear(rgba: Vec4) -> Self {
+        rgba
+    }
+
+    fn to_linear(self) -> Vec4 {
+        self
+    }
+}
+
 #[derive(Copy, Clone)]
 pub struct Color {
     pub a: u8,
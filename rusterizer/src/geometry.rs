@@ -1,484 +1,706 @@
-use std::{
-    collections::HashMap,
-    ops::{Add, Mul, MulAssign, Sub},
-};
+use std::ops::Range;
 
 use crate::{
-    color::{self, Color},
-    utils::{lerp, map_to_range, to_argb8},
-    Texture,
+    binner::Binner,
+    color::ColorFormat,
+    shader::{Fragment, FragmentShader, Varyings, VertexOutput, VertexShader},
+    utils::{lerp, map_to_range},
 };
-use glam::{Mat4, Vec2, Vec3, Vec3Swizzles, Vec4Swizzles};
+use glam::{I64Vec2, I64Vec3, Vec2, Vec3, Vec4};
 use shared::{
-    camera::Camera,
-    mesh::{Mesh, Vertex},
-    transform::Transform,
-    *,
+    framebuffer::{Framebuffer, MAX_SAMPLES},
+    mesh::{Mesh, MeshVertex, Vertex},
 };
 
-use crate::{barycentric_coordinates, edge_function_cw, WIDTH};
+use crate::utils::{sample_positions, snap_to_subpixel, EdgeFunction, SUBPIXEL_ONE};
 
+/// A triangle of vertex shader outputs, in clip space.
 #[derive(Debug, Copy, Clone)]
-pub struct Triangle {
-    v0: Vertex,
-    v1: Vertex,
-    v2: Vertex,
-}
-
-pub enum VerticesOrder {
-    ABC,
-    ACB,
-    BAC,
-    BCA,
-    CAB,
-    CBA,
+pub struct Triangle<V> {
+    v0: VertexOutput<V>,
+    v1: VertexOutput<V>,
+    v2: VertexOutput<V>,
 }
 
-impl Triangle {
-    fn new(v0: Vertex, v2: Vertex, v1: Vertex) -> Self {
+impl<V: Varyings> Triangle<V> {
+    pub fn new(v0: VertexOutput<V>, v1: VertexOutput<V>, v2: VertexOutput<V>) -> Self {
         Self { v0, v1, v2 }
     }
-    pub fn from_vertices(vertices: [&Vertex; 3]) -> Self {
+    pub fn from_vertices(vertices: [&VertexOutput<V>; 3]) -> Self {
         Triangle {
             v0: *vertices[0],
             v1: *vertices[1],
             v2: *vertices[2],
         }
     }
-    pub fn transform(&mut self, matrix: &Mat4) {
-        self.v0.position = *matrix * self.v0.position;
-        self.v1.position = *matrix * self.v1.position;
-        self.v2.position = *matrix * self.v2.position;
+}
+
+/// Side planes are only clipped against once a vertex leaves this multiple of the viewport,
+/// anything in between is cut off by the rasterizer's bounds instead.
+pub const GUARD_BAND: f32 = 4.0;
+
+/// The six planes of the clip-space view volume, using glam's `[0, w]` depth range.
+#[derive(Debug, Copy, Clone)]
+pub enum ClipPlane {
+    Near,
+    Far,
+    Left,
+    Right,
+    Bottom,
+    Top,
+}
+
+impl ClipPlane {
+    pub const ALL: [ClipPlane; 6] = [
+        ClipPlane::Near,
+        ClipPlane::Far,
+        ClipPlane::Left,
+        ClipPlane::Right,
+        ClipPlane::Bottom,
+        ClipPlane::Top,
+    ];
+
+    /// Signed distance of a clip-space position to the plane, positive on the inside.
+    /// The side planes are scaled by `extent`: 1.0 for the view frustum, `GUARD_BAND` for clipping.
+    pub fn distance(&self, position: Vec4, extent: f32) -> f32 {
+        match self {
+            ClipPlane::Near => position.z,
+            ClipPlane::Far => position.w - position.z,
+            ClipPlane::Left => position.x + extent * position.w,
+            ClipPlane::Right => extent * position.w - position.x,
+            ClipPlane::Bottom => position.y + extent * position.w,
+            ClipPlane::Top => extent * position.w - position.y,
+        }
     }
-    pub fn reorder(&self, order: VerticesOrder) -> Self {
-        match order {
-            VerticesOrder::ABC => *self,
-            VerticesOrder::ACB => Self::new(self.v0, self.v2, self.v1),
-            VerticesOrder::BAC => Self::new(self.v1, self.v0, self.v2),
-            VerticesOrder::BCA => Self::new(self.v1, self.v2, self.v0),
-            VerticesOrder::CAB => Self::new(self.v2, self.v0, self.v1),
-            VerticesOrder::CBA => Self::new(self.v2, self.v1, self.v0),
+
+    fn extent(&self) -> f32 {
+        match self {
+            ClipPlane::Near | ClipPlane::Far => 1.0,
+            _ => GUARD_BAND,
         }
     }
 }
 
-pub enum ClipResult {
+pub enum ClipResult<V> {
     None,
-    One(Triangle),
-    Two((Triangle, Triangle)),
+    One(Triangle<V>),
+    /// Convex polygon left after clipping, triangulated as a fan around its first vertex.
+    Fan(Vec<VertexOutput<V>>),
 }
 
-pub fn cull_triangle_view_frustum(triangle: &Triangle) -> bool {
-    // X-Axis
-    if triangle.v0.position.x > triangle.v0.position.w
-        && triangle.v1.position.x > triangle.v1.position.w
-        && triangle.v2.position.x > triangle.v2.position.w
-    {
-        return true;
-    }
-    if triangle.v0.position.x < -triangle.v0.position.w
-        && triangle.v1.position.x < -triangle.v1.position.w
-        && triangle.v2.position.x < -triangle.v2.position.w
-    {
-        return true;
-    }
-    // Y-Axis
-    if triangle.v0.position.y > triangle.v0.position.w
-        && triangle.v1.position.y > triangle.v1.position.w
-        && triangle.v2.position.y > triangle.v2.position.w
-    {
-        return true;
-    }
-    if triangle.v0.position.y < -triangle.v0.position.w
-        && triangle.v1.position.y < -triangle.v1.position.w
-        && triangle.v2.position.y < -triangle.v2.position.w
+impl<V: Varyings> ClipResult<V> {
+    pub fn for_each_triangle<F>(&self, mut f: F)
+    where
+        F: FnMut(&Triangle<V>),
     {
-        return true;
-    }
-    // Z-Axis
-    if triangle.v0.position.z > triangle.v0.position.w
-        && triangle.v1.position.z > triangle.v1.position.w
-        && triangle.v2.position.z > triangle.v2.position.w
-    {
-        return true;
-    }
-    if triangle.v0.position.z < -triangle.v0.position.w
-        && triangle.v1.position.z < -triangle.v1.position.w
-        && triangle.v2.position.z < -triangle.v2.position.w
-    {
-        return true;
+        match self {
+            ClipResult::None => {}
+            ClipResult::One(triangle) => f(triangle),
+            ClipResult::Fan(vertices) => {
+                for i in 1..vertices.len() - 1 {
+                    f(&Triangle::from_vertices([
+                        &vertices[0],
+                        &vertices[i],
+                        &vertices[i + 1],
+                    ]));
+                }
+            }
+        }
     }
+}
 
-    if triangle.v0.position.z < 0.0 && triangle.v1.position.z < 0.0 && triangle.v2.position.z < 0.0
-    {
-        return true;
+pub fn cull_triangle_view_frustum<V>(triangle: &Triangle<V>) -> bool {
+    let positions = [
+        triangle.v0.position,
+        triangle.v1.position,
+        triangle.v2.position,
+    ];
+
+    // Culled if all vertices are outside of the same plane
+    ClipPlane::ALL.iter().any(|plane| {
+        positions
+            .iter()
+            .all(|position| plane.distance(*position, 1.0) < 0.0)
+    })
+}
+
+/// Sutherland-Hodgman: clips a convex polygon against a single plane, keeping its winding.
+pub fn clip_polygon<V: Varyings>(
+    vertices: &[VertexOutput<V>],
+    plane: ClipPlane,
+) -> Vec<VertexOutput<V>> {
+    let extent = plane.extent();
+    let mut clipped = Vec::with_capacity(vertices.len() + 1);
+
+    let mut previous = vertices[vertices.len() - 1];
+    let mut previous_distance = plane.distance(previous.position, extent);
+    for current in vertices {
+        let distance = plane.distance(current.position, extent);
+        if (previous_distance >= 0.0) != (distance >= 0.0) {
+            let alpha = previous_distance / (previous_distance - distance);
+            clipped.push(lerp(previous, *current, alpha));
+        }
+        if distance >= 0.0 {
+            clipped.push(*current);
+        }
+        previous = *current;
+        previous_distance = distance;
     }
 
-    false
+    clipped
 }
 
-pub fn cull_triangle_backface(triangle: &Triangle, view_dir: Vec3) -> bool {
-    let mut normal = (triangle.v1.position.xyz() - triangle.v0.position.xyz())
-        .cross(triangle.v2.position.xyz() - triangle.v0.position.xyz());
+/// Culls triangles outside of the view frustum and clips the rest. Face culling happens later,
+/// in `TriangleSetup`, once the screen-space winding is known.
+pub fn clip_cull_triangle<V: Varyings>(triangle: &Triangle<V>) -> ClipResult<V> {
+    // Frustum culling
+    if cull_triangle_view_frustum(triangle) {
+        return ClipResult::None;
+    }
 
-    normal = normal.normalize();
+    let vertices = [triangle.v0, triangle.v1, triangle.v2];
+    let crossed_planes = ClipPlane::ALL.iter().filter(|plane| {
+        vertices
+            .iter()
+            .any(|vertex| plane.distance(vertex.position, plane.extent()) < 0.0)
+    });
+
+    let mut polygon: Option<Vec<VertexOutput<V>>> = None;
+    for plane in crossed_planes {
+        let clipped = clip_polygon(polygon.as_deref().unwrap_or(&vertices), *plane);
+        if clipped.len() < 3 {
+            return ClipResult::None;
+        }
+        polygon = Some(clipped);
+    }
 
-    normal.dot(-Vec3::Z) >= 0.0
+    match polygon {
+        Some(vertices) => ClipResult::Fan(vertices),
+        // Return original triangle
+        None => ClipResult::One(*triangle),
+    }
 }
 
-enum ClipEvaluation {
-    AllPositive,
-    OneNegative(u32),
-    TwoNegative(u32, u32),
-    AllNegative,
+/// Which faces are discarded before rasterization.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum CullMode {
+    None,
+    Front,
+    Back,
 }
 
-fn evaluate_clip_vertices(triangle: &Triangle) -> ClipEvaluation {
-    let mut negative_count: u32 = 3;
-    let mut negative_indices = [true; 3];
+/// Winding order of front faces as seen on screen.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FrontFace {
+    Ccw,
+    Cw,
+}
 
-    if triangle.v0.position.z > 0.0 {
-        negative_indices[0] = false;
-        negative_count -= 1;
-    }
-    if triangle.v1.position.z > 0.0 {
-        negative_indices[1] = false;
-        negative_count -= 1;
-    }
-    if triangle.v2.position.z > 0.0 {
-        negative_indices[2] = false;
-        negative_count -= 1;
-    }
+/// How a fragment's color is combined with the color already in the target.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum BlendMode {
+    /// The fragment replaces the target's color.
+    Replace,
+    /// The fragment is blended over the target's color by its alpha, in linear light.
+    Alpha,
+}
 
-    if negative_count == 3 {
-        return ClipEvaluation::AllNegative;
-    }
-    if negative_count == 0 {
-        return ClipEvaluation::AllPositive;
-    }
+/// Fixed-function state of a draw call. Shader inputs are passed as uniforms instead.
+pub struct RenderState {
+    pub cull_mode: CullMode,
+    pub front_face: FrontFace,
+    pub blend_mode: BlendMode,
+    /// Whether fragments passing the depth test write their depth. Translucent surfaces are
+    /// usually tested against the depth buffer without writing to it.
+    pub depth_write: bool,
+}
 
-    if negative_count == 1 {
-        for i in 0..negative_indices.len() {
-            if negative_indices[i] {
-                return ClipEvaluation::OneNegative(i as u32);
-            }
+impl RenderState {
+    pub fn new() -> RenderState {
+        RenderState {
+            cull_mode: CullMode::Back,
+            front_face: FrontFace::Ccw,
+            blend_mode: BlendMode::Replace,
+            depth_write: true,
         }
     }
+}
 
-    if negative_count == 2 {
-        if !negative_indices[0] {
-            return ClipEvaluation::TwoNegative(1, 2);
-        }
-        if !negative_indices[1] {
-            return ClipEvaluation::TwoNegative(0, 2);
-        }
-        if !negative_indices[2] {
-            return ClipEvaluation::TwoNegative(0, 1);
-        }
+impl Default for RenderState {
+    fn default() -> Self {
+        Self::new()
     }
-    return ClipEvaluation::AllNegative;
 }
 
-pub fn clip_triangle_one_negative(triangle: &Triangle) -> (Triangle, Triangle) {
-    let alpha_a = (-triangle.v0.position.z) / (triangle.v1.position.z - triangle.v0.position.z);
-    let alpha_b = (-triangle.v0.position.z) / (triangle.v2.position.z - triangle.v0.position.z);
+// Coverage masks have a bit per sample
+const _: () = assert!(MAX_SAMPLES <= u32::BITS as usize);
 
-    let v0_a = lerp(triangle.v0, triangle.v1, alpha_a);
-    let v0_b = lerp(triangle.v0, triangle.v2, alpha_b);
+/// Samples of a pixel covered by a triangle, handed out by `TriangleSetup::rasterize`.
+#[derive(Debug, Copy, Clone)]
+pub struct Coverage {
+    pub x: usize,
+    pub y: usize,
+    /// Bit `i` is set when sample `i` is inside the triangle.
+    pub mask: u32,
+    /// Depth at every covered sample, from 0 at the near plane to 1 at the far plane.
+    pub depth: [f32; MAX_SAMPLES],
+    /// Barycentric coordinates and perspective correction at the point the pixel is shaded.
+    /// That is the pixel center if covered, otherwise the first covered sample.
+    pub barycentric: Vec3,
+    pub correction: f32,
+    /// Change of the perspective-correct weights to the next pixel in x and y, within the
+    /// pixel's 2x2 quad.
+    pub ddx: Vec3,
+    pub ddy: Vec3,
+}
 
-    let mut result_a = *triangle;
-    let mut result_b = *triangle;
+/// Screen-space setup of a clipped triangle, shared by the immediate and the binned rasterizer.
+#[derive(Debug, Copy, Clone)]
+pub struct TriangleSetup<V> {
+    /// Varyings divided by w, for perspective-correct interpolation.
+    varyings: [V; 3],
+    rec: Vec3,
+    /// Normalized device depth of the vertices, which is linear in screen space.
+    z: Vec3,
+    edges: [EdgeFunction; 3],
+    area: i64,
+    front_facing: bool,
+    blend_mode: BlendMode,
+    depth_write: bool,
+    pub bounds: BoundingBox2D,
+}
 
-    result_a.v0 = v0_a;
+impl<V: Varyings> TriangleSetup<V> {
+    /// Returns `None` for degenerate triangles and triangles removed by the render state's
+    /// face culling.
+    pub fn new(triangle: &Triangle<V>, viewport: Vec2, render_state: &RenderState) -> Option<Self> {
+        let rec0 = 1.0 / triangle.v0.position.w;
+        let rec1 = 1.0 / triangle.v1.position.w;
+        let rec2 = 1.0 / triangle.v2.position.w;
+
+        // This would be the output of the vertex shader (clip space)
+        // then we perform perspective division to transform in ndc
+        // now x,y,z componend of ndc are between -1 and 1
+        let ndc0 = triangle.v0.position * rec0;
+        let ndc1 = triangle.v1.position * rec1;
+        let ndc2 = triangle.v2.position * rec2;
+
+        let v0 = triangle.v0.varyings * rec0;
+        let v1 = triangle.v1.varyings * rec1;
+        let v2 = triangle.v2.varyings * rec2;
+
+        // screeen coordinates remapped to window
+        let sc0 = glam::vec2(
+            map_to_range(ndc0.x, -1.0, 1.0, 0.0, viewport.x),
+            map_to_range(-ndc0.y, -1.0, 1.0, 0.0, viewport.y),
+        );
+        let sc1 = glam::vec2(
+            map_to_range(ndc1.x, -1.0, 1.0, 0.0, viewport.x),
+            map_to_range(-ndc1.y, -1.0, 1.0, 0.0, viewport.y),
+        );
+        let sc2 = glam::vec2(
+            map_to_range(ndc2.x, -1.0, 1.0, 0.0, viewport.x),
+            map_to_range(-ndc2.y, -1.0, 1.0, 0.0, viewport.y),
+        );
 
-    result_b.v0 = v0_a;
-    result_b.v1 = v0_b;
+        // Snap to the subpixel grid, so coverage no longer depends on float rounding
+        let fx0 = snap_to_subpixel(sc0);
+        let fx1 = snap_to_subpixel(sc1);
+        let fx2 = snap_to_subpixel(sc2);
+
+        // Edge i is opposite to vertex i, so its value is the (unnormalized) weight of vertex i.
+        // The signed area is positive for counter-clockwise triangles on screen.
+        let mut edges = [
+            EdgeFunction::new(fx1, fx2),
+            EdgeFunction::new(fx2, fx0),
+            EdgeFunction::new(fx0, fx1),
+        ];
+        let mut area = edges[2].evaluate(fx2);
+        if area == 0 {
+            return None;
+        }
 
-    (result_a, result_b)
-}
+        let front_facing = match render_state.front_face {
+            FrontFace::Ccw => area > 0,
+            FrontFace::Cw => area < 0,
+        };
+        let culled = match render_state.cull_mode {
+            CullMode::None => false,
+            CullMode::Front => front_facing,
+            CullMode::Back => !front_facing,
+        };
+        if culled {
+            return None;
+        }
 
-pub fn clip_triangle_two_negatives(triangle: &Triangle) -> Triangle {
-    let alpha_a = (-triangle.v0.position.z) / (triangle.v2.position.z - triangle.v0.position.z);
-    let alpha_b = (-triangle.v1.position.z) / (triangle.v2.position.z - triangle.v1.position.z);
+        // Reverse the edges of clockwise triangles, so the inside is always positive.
+        if area < 0 {
+            edges = [
+                EdgeFunction::new(fx2, fx1),
+                EdgeFunction::new(fx0, fx2),
+                EdgeFunction::new(fx1, fx0),
+            ];
+            area = -area;
+        }
 
-    let v0 = lerp(triangle.v0, triangle.v2, alpha_a);
-    let v1 = lerp(triangle.v1, triangle.v2, alpha_b);
+        let snapped = [fx0, fx1, fx2].map(|p| p.as_vec2() / SUBPIXEL_ONE as f32);
+        let mut bounds = BoundingBox2D::get_bounds_from_triangle(&snapped);
+        bounds.clamp(Vec2::ZERO, viewport);
+
+        Some(Self {
+            varyings: [v0, v1, v2],
+            rec: Vec3::new(rec0, rec1, rec2),
+            z: Vec3::new(ndc0.z, ndc1.z, ndc2.z),
+            edges,
+            area,
+            front_facing,
+            blend_mode: render_state.blend_mode,
+            depth_write: render_state.depth_write,
+            bounds,
+        })
+    }
 
-    Triangle {
-        v0,
-        v1,
-        v2: triangle.v2,
+    pub fn is_front_facing(&self) -> bool {
+        self.front_facing
     }
-}
 
-pub fn clip_cull_triangle(triangle: &Triangle, view_dir: Vec3) -> ClipResult {
-    // Backface culling
-    if cull_triangle_backface(&triangle, view_dir) {
-        return ClipResult::None;
+    /// Pixel columns and rows touched by the triangle's bounding box.
+    pub fn pixel_range(&self) -> (Range<usize>, Range<usize>) {
+        (
+            self.bounds.min.x as usize..self.bounds.max.x.ceil() as usize,
+            self.bounds.min.y as usize..self.bounds.max.y.ceil() as usize,
+        )
     }
-    // Frustum culling
-    if cull_triangle_view_frustum(triangle) {
-        return ClipResult::None;
-    } else {
-        let evaluated = evaluate_clip_vertices(triangle);
-        // TODO: Clip Triangle
-        match evaluated {
-            ClipEvaluation::OneNegative(first) => {
-                if first == 0 {
-                    return ClipResult::Two(clip_triangle_one_negative(
-                        &triangle.reorder(VerticesOrder::ACB),
-                    ));
-                } else if first == 1 {
-                    return ClipResult::Two(clip_triangle_one_negative(
-                        &triangle.reorder(VerticesOrder::BAC),
-                    ));
-                } else if first == 2 {
-                    return ClipResult::Two(clip_triangle_one_negative(
-                        &triangle.reorder(VerticesOrder::CBA),
-                    ));
-                }
-            }
-            ClipEvaluation::TwoNegative(first, second) => {
-                if first == 0 && second == 1 {
-                    return ClipResult::One(clip_triangle_two_negatives(&triangle));
-                }
-                if first == 0 && second == 2 {
-                    return ClipResult::One(clip_triangle_two_negatives(
-                        &triangle.reorder(VerticesOrder::ACB),
-                    ));
-                }
-                if first == 1 && second == 2 {
-                    return ClipResult::One(clip_triangle_two_negatives(
-                        &triangle.reorder(VerticesOrder::BCA),
-                    ));
-                }
-            }
-            ClipEvaluation::AllPositive => {}
-            ClipEvaluation::AllNegative => {}
-        }
+
+    /// Screen-space barycentric coordinates and the perspective correction, the interpolated w.
+    fn interpolate_depth(&self, weights: I64Vec3) -> (Vec3, f32) {
+        let b = weights.as_vec3() / self.area as f32;
+        let correction = b.x * self.rec.x + b.y * self.rec.y + b.z * self.rec.z;
+        (b, 1.0 / correction)
     }
 
-    // Return original triangle
-    return ClipResult::One(*triangle);
-}
+    fn depth(&self, barycentric: Vec3) -> f32 {
+        barycentric.dot(self.z)
+    }
 
-pub fn draw_triangle(
-    vertices: [&Vertex; 3],
-    render_state: &RenderState,
-    transform: &Transform,
-    cam: &Camera,
-    viewport: Vec2,
-    zbuff: &mut Vec<f32>,
-) {
-    let mvp = cam.projection() * cam.view() * transform.local();
+    fn covers(&self, weights: I64Vec3) -> bool {
+        let [e0, e1, e2] = &self.edges;
+        e0.covers(weights.x) && e1.covers(weights.y) && e2.covers(weights.z)
+    }
 
-    let mut triangle = Triangle::from_vertices(vertices);
-    triangle.transform(&mvp);
+    /// Bit mask of the samples covered, for the pixel with edge values `center` at its center.
+    fn sample_mask(&self, center: I64Vec3, sample_offsets: &[I64Vec3]) -> u32 {
+        sample_offsets
+            .iter()
+            .enumerate()
+            .filter(|(_, offset)| self.covers(center + **offset))
+            .fold(0, |mask, (sample, _)| mask | (1 << sample))
+    }
+
+    /// Calls `fragment` with the coverage of every pixel inside the given ranges that has at
+    /// least one of its samples covered. Coverage is tested at the standard sample positions
+    /// for the sample count.
+    ///
+    /// Pixels are visited in 2x2 quads aligned to even coordinates, which provide the
+    /// derivatives of the interpolated values.
+    pub fn rasterize<F>(
+        &self,
+        columns: Range<usize>,
+        rows: Range<usize>,
+        samples: usize,
+        mut fragment: F,
+    ) where
+        F: FnMut(&Coverage),
+    {
+        if columns.is_empty() || rows.is_empty() {
+            return;
+        }
 
-    let result = clip_cull_triangle(&triangle, -cam.transform.forward());
+        let [e0, e1, e2] = &self.edges;
+        let step_x = I64Vec3::new(e0.a, e1.a, e2.a);
+        let step_y = I64Vec3::new(e0.b, e1.b, e2.b);
+        let (pixel_x, pixel_y) = (step_x * SUBPIXEL_ONE, step_y * SUBPIXEL_ONE);
 
-    match result {
-        ClipResult::None => {}
-        ClipResult::One(tri) => {
-            draw_triangle_clipped(&tri, render_state, viewport, zbuff);
+        // Edge offsets from the pixel center to every sample
+        let mut sample_offsets = [I64Vec3::ZERO; MAX_SAMPLES];
+        for (offset, position) in sample_offsets.iter_mut().zip(sample_positions(samples)) {
+            let position = (*position * SUBPIXEL_ONE as f32).as_i64vec2();
+            *offset = step_x * position.x + step_y * position.y;
         }
-        ClipResult::Two(tri) => {
-            draw_triangle_clipped(&tri.0, render_state, viewport, zbuff);
-            draw_triangle_clipped(&tri.1, render_state, viewport, zbuff);
+        let sample_offsets = &sample_offsets[..samples];
+
+        // Evaluate the edges once at the first pixel center, then step them incrementally.
+        // Integer steps are exact, so the result does not depend on where the ranges start.
+        let (quad_columns, quad_rows) =
+            (columns.start & !1..columns.end, rows.start & !1..rows.end);
+        let start = I64Vec2::new(quad_columns.start as i64, quad_rows.start as i64) * SUBPIXEL_ONE
+            + SUBPIXEL_ONE / 2;
+        let mut row = I64Vec3::new(e0.evaluate(start), e1.evaluate(start), e2.evaluate(start));
+
+        for quad_y in quad_rows.step_by(2) {
+            let mut quad = row;
+            for quad_x in quad_columns.clone().step_by(2) {
+                // Edge values at the pixel centers of the quad, row by row
+                let centers = [
+                    quad,
+                    quad + pixel_x,
+                    quad + pixel_y,
+                    quad + pixel_x + pixel_y,
+                ];
+                let mut masks = [0; 4];
+                for (i, center) in centers.iter().enumerate() {
+                    let (x, y) = (quad_x + (i & 1), quad_y + (i >> 1));
+                    if columns.contains(&x) && rows.contains(&y) {
+                        masks[i] = self.sample_mask(*center, sample_offsets);
+                    }
+                }
+
+                if masks != [0; 4] {
+                    // Uncovered pixels of the quad still take part in the derivatives
+                    let interpolated = centers.map(|center| self.interpolate_depth(center));
+                    let weights =
+                        interpolated.map(|(barycentric, correction)| barycentric * correction);
+                    let finite_or_zero = |d: Vec3| if d.is_finite() { d } else { Vec3::ZERO };
+
+                    for (i, &mask) in masks.iter().enumerate().filter(|(_, &mask)| mask != 0) {
+                        let mut coverage = Coverage {
+                            x: quad_x + (i & 1),
+                            y: quad_y + (i >> 1),
+                            mask,
+                            depth: [f32::INFINITY; MAX_SAMPLES],
+                            barycentric: interpolated[i].0,
+                            correction: interpolated[i].1,
+                            ddx: finite_or_zero(weights[(i & 2) + 1] - weights[i & 2]),
+                            ddy: finite_or_zero(weights[(i & 1) + 2] - weights[i & 1]),
+                        };
+                        for (sample, offset) in sample_offsets.iter().enumerate() {
+                            if mask & (1 << sample) != 0 {
+                                coverage.depth[sample] = match *offset {
+                                    I64Vec3::ZERO => self.depth(interpolated[i].0),
+                                    offset => {
+                                        self.depth(self.interpolate_depth(centers[i] + offset).0)
+                                    }
+                                };
+                            }
+                        }
+                        // Shade at the first covered sample if the center is outside
+                        if !self.covers(centers[i]) {
+                            let first = sample_offsets[mask.trailing_zeros() as usize];
+                            (coverage.barycentric, coverage.correction) =
+                                self.interpolate_depth(centers[i] + first);
+                        }
+                        fragment(&coverage);
+                    }
+                }
+                quad += pixel_x * 2;
+            }
+            row += pixel_y * 2;
         }
     }
-}
-
-type ShadeFn = fn(&RenderState, [&Vertex; 3], Vec3, f32) -> u32;
-pub struct RenderState<'a> {
-    pub texture: Option<&'a Texture>,
-    shade_fn: ShadeFn,
-    draw_fn: FnPtrDraw,
-    pub clear_color: Color,
-    pub variables: HashMap<&'static str, f32>,
-}
 
-impl RenderState<'_> {
-    pub fn from_shade_fn<'a>(
-        shared: &'a State,
-        shade_fn: ShadeFn,
-        texture: Option<&'a Texture>,
-    ) -> RenderState<'a> {
-        RenderState {
-            texture,
-            shade_fn,
-            draw_fn: shared.draw_fn,
-            clear_color: Color::from_argb8(shared.clear_color),
-            variables: HashMap::new(),
+    /// Interpolates the varyings for a pixel handed out by `rasterize`.
+    pub fn fragment(&self, coverage: &Coverage) -> Fragment<V> {
+        let [v0, v1, v2] = self.varyings;
+        let b = coverage.barycentric;
+        let varyings = (v0 * b.x + v1 * b.y + v2 * b.z) * coverage.correction;
+        let (dx, dy) = (coverage.ddx, coverage.ddy);
+
+        Fragment {
+            varyings,
+            ddx: v0 * dx.x + v1 * dx.y + v2 * dx.z,
+            ddy: v0 * dy.x + v1 * dy.y + v2 * dy.z,
+            position: Vec2::new(coverage.x as f32, coverage.y as f32) + 0.5,
+            depth: self.depth(b),
+            front_facing: self.front_facing,
         }
     }
-    pub fn draw_texture<'a>(shared: &'a State, texture: Option<&'a Texture>) -> RenderState<'a> {
-        RenderState {
-            texture,
-            shade_fn: draw_texture,
-            draw_fn: shared.draw_fn,
-            clear_color: Color::from_argb8(shared.clear_color),
-            variables: HashMap::new(),
+
+    /// Depth tests the covered samples of a pixel against `depth` and shades the pixel once if
+    /// any of them pass. Passing samples receive the shaded color, blended as the render state
+    /// asks, and their depth if it is written. Without a color attachment `color` is empty and
+    /// only depth is written.
+    pub fn shade<U, F, C>(
+        &self,
+        coverage: &Coverage,
+        uniforms: &U,
+        shader: &F,
+        color: &mut [C],
+        depth: &mut [f32],
+    ) where
+        F: FragmentShader<U, C, Varyings = V>,
+        C: ColorFormat,
+    {
+        let passed: u32 = (0..depth.len())
+            .filter(|&sample| {
+                coverage.mask & (1 << sample) != 0 && coverage.depth[sample] < depth[sample]
+            })
+            .fold(0, |mask, sample| mask | (1 << sample));
+        if passed == 0 {
+            return;
+        }
+
+        let fragment = self.fragment(coverage);
+        if let Some(shaded) = shader.fragment(uniforms, &fragment) {
+            for (sample, depth) in depth.iter_mut().enumerate() {
+                if passed & (1 << sample) != 0 {
+                    if self.depth_write {
+                        *depth = coverage.depth[sample];
+                    }
+                    if let Some(color) = color.get_mut(sample) {
+                        *color = match self.blend_mode {
+                            BlendMode::Replace => shaded,
+                            BlendMode::Alpha => blend_over(shaded, *color),
+                        };
+                    }
+                }
+            }
         }
     }
-}
 
-pub fn draw_texture(
-    state: &RenderState,
-    vertices: [&Vertex; 3],
-    bary_centric: Vec3,
-    correction: f32,
-) -> u32 {
-    let v0 = vertices[0];
-    let v1 = vertices[1];
-    let v2 = vertices[2];
-
-    match state.texture {
-        Some(texture) => {
-            let tex_coords =
-                bary_centric.x * v0.uv + bary_centric.y * v1.uv + bary_centric.z * v2.uv;
-            let tex_coords = tex_coords * correction;
-            let col = texture.argb_at_uv(tex_coords.x, tex_coords.y);
-            let mut col = Color::from_argb8(col);
-            let alpha = col.a as f32 / 255.0;
-            col.r = lerp(state.clear_color.r as f32, col.r as f32, alpha) as u8;
-            col.g = lerp(state.clear_color.g as f32, col.g as f32, alpha) as u8;
-            col.b = lerp(state.clear_color.b as f32, col.b as f32, alpha) as u8;
-
-            col.to_argb8()
+    /// Depth tests the covered samples of a pixel against `depth` and writes the depth of the
+    /// samples that pass, without running a fragment shader.
+    pub fn write_depth(&self, coverage: &Coverage, depth: &mut [f32]) {
+        if !self.depth_write {
+            return;
         }
-        None => {
-            let vertex_color =
-                bary_centric.x * v0.color + bary_centric.y * v1.color + bary_centric.z * v2.color;
-            let vertex_color = vertex_color * correction;
-            to_argb8(
-                255,
-                (vertex_color.x * 255.0) as u8,
-                (vertex_color.y * 255.0) as u8,
-                (vertex_color.z * 255.0) as u8,
-            )
+        for (sample, depth) in depth.iter_mut().enumerate() {
+            if coverage.mask & (1 << sample) != 0 && coverage.depth[sample] < *depth {
+                *depth = coverage.depth[sample];
+            }
         }
     }
 }
 
-pub fn draw_vertex_color(
-    state: &RenderState,
-    vertices: [&Vertex; 3],
-    bary_centric: Vec3,
-    correction: f32,
-) -> u32 {
-    let v0 = vertices[0];
-    let v1 = vertices[1];
-    let v2 = vertices[2];
-
-    let vertex_color =
-        bary_centric.x * v0.color + bary_centric.y * v1.color + bary_centric.z * v2.color;
-    let vertex_color = vertex_color * correction;
-    to_argb8(
-        255,
-        (vertex_color.x * 255.0) as u8,
-        (vertex_color.y * 255.0) as u8,
-        (vertex_color.z * 255.0) as u8,
-    )
+/// Composites `source` over `destination` by the source's alpha, in linear light.
+fn blend_over<C: ColorFormat>(source: C, destination: C) -> C {
+    let source = source.to_linear();
+    let destination = destination.to_linear();
+    let alpha = source.w;
+    let rgb = source.truncate() * alpha + destination.truncate() * (1.0 - alpha);
+    C::from_linear(rgb.extend(alpha + destination.w * (1.0 - alpha)))
 }
 
-pub fn draw_triangle_clipped(
-    triangle: &Triangle,
+pub fn draw_triangle_clipped<U, F: FragmentShader<U, C>, C: ColorFormat>(
+    triangle: &Triangle<F::Varyings>,
     render_state: &RenderState,
-    viewport: Vec2,
-    zbuff: &mut [f32],
+    uniforms: &U,
+    shader: &F,
+    target: &mut Framebuffer<C>,
 ) {
-    let rec0 = 1.0 / triangle.v0.position.w;
-    let rec1 = 1.0 / triangle.v1.position.w;
-    let rec2 = 1.0 / triangle.v2.position.w;
-
-    // This would be the output of the vertex shader (clip space)
-    // then we perform perspective division to transform in ndc
-    // now x,y,z componend of ndc are between -1 and 1
-    let ndc0 = triangle.v0.position * rec0;
-    let ndc1 = triangle.v1.position * rec1;
-    let ndc2 = triangle.v2.position * rec2;
-
-    let v0 = triangle.v0 * rec0;
-    let v1 = triangle.v1 * rec1;
-    let v2 = triangle.v2 * rec2;
-
-    // screeen coordinates remapped to window
-    let sc0 = glam::vec2(
-        map_to_range(ndc0.x, -1.0, 1.0, 0.0, viewport.x),
-        map_to_range(-ndc0.y, -1.0, 1.0, 0.0, viewport.y),
-    );
-    let sc1 = glam::vec2(
-        map_to_range(ndc1.x, -1.0, 1.0, 0.0, viewport.x),
-        map_to_range(-ndc1.y, -1.0, 1.0, 0.0, viewport.y),
-    );
-    let sc2 = glam::vec2(
-        map_to_range(ndc2.x, -1.0, 1.0, 0.0, viewport.x),
-        map_to_range(-ndc2.y, -1.0, 1.0, 0.0, viewport.y),
-    );
-
-    let mut bounds = BoundingBox2D::get_bounds_from_triangle(&[sc0, sc1, sc2]);
-    bounds.clamp(Vec2::ZERO, viewport);
-
-    // Loop over positions instead of pixels, to only update the part of the screen that is needed.
-    for y in bounds.min.y as usize..bounds.max.y as usize {
-        for x in bounds.min.x as usize..bounds.max.x as usize {
-            let coords = Vec2::new(x as f32, y as f32) + 0.5;
-            let pixel_id = coords_to_index(coords);
-
-            // Ensure pixel is within bounds
-            if pixel_id >= WIDTH * HEIGHT {
-                continue;
-            }
-
-            let area = edge_function_cw(sc0, sc1, sc2);
-            let bary = barycentric_coordinates(coords, sc0, sc1, sc2, area);
-            if let Some(b) = bary {
-                let correction = b.x * rec0 + b.y * rec1 + b.z * rec2;
-                let correction = 1.0 / correction;
-                let depth = correction;
-
-                if depth < zbuff[pixel_id] {
-                    zbuff[pixel_id] = depth;
-                    let color =
-                        (render_state.shade_fn)(render_state, [&v0, &v1, &v2], b, correction);
-                    (render_state.draw_fn)(x as u16, y as u16, color);
-                }
-            }
-        }
-    }
+    let viewport = Vec2::new(target.width as f32, target.height as f32);
+    let Some(setup) = TriangleSetup::new(triangle, viewport, render_state) else {
+        return;
+    };
+    let (columns, rows) = setup.pixel_range();
+    let samples = target.samples;
+
+    setup.rasterize(columns, rows, samples, |coverage| {
+        let first = target.index(coverage.x, coverage.y) * samples;
+        let pixel = first..first + samples;
+        let color = match target.has_color() {
+            true => pixel.clone(),
+            false => 0..0,
+        };
+        setup.shade(
+            coverage,
+            uniforms,
+            shader,
+            &mut target.color[color],
+            &mut target.depth[pixel],
+        );
+    });
 }
 
-pub struct RenderMesh<'a> {
-    mesh: &'a Mesh,
+pub struct RenderMesh<'a, V = Vertex> {
+    mesh: &'a Mesh<V>,
 }
 
-impl RenderMesh<'_> {
-    pub fn from_mesh(mesh: &Mesh) -> RenderMesh {
+impl<V: MeshVertex> RenderMesh<'_, V> {
+    pub fn from_mesh(mesh: &Mesh<V>) -> RenderMesh<'_, V> {
         RenderMesh { mesh }
     }
 
-    pub fn draw_mesh(
+    /// Runs the vertex shader over the mesh and clips the resulting triangles.
+    fn process_vertices<U, S: VertexShader<U, V>>(
         &self,
-        render_state: &RenderState,
-        cam: &Camera,
-        viewport: Vec2,
-        zbuff: &mut Vec<f32>,
-    ) {
+        uniforms: &U,
+        shader: &S,
+    ) -> Vec<Triangle<S::Varyings>> {
+        let outputs: Vec<VertexOutput<S::Varyings>> = self
+            .mesh
+            .vertices
+            .iter()
+            .map(|vertex| shader.vertex(uniforms, vertex))
+            .collect();
+
+        let mut triangles = Vec::with_capacity(self.mesh.triangles.len());
         for triangle in &self.mesh.triangles {
-            let vertices = self.mesh.get_triangle_vertices(*triangle);
-            draw_triangle(
-                vertices,
-                render_state,
-                &self.mesh.transform,
-                cam,
-                viewport,
-                zbuff,
-            );
+            let triangle = Triangle::from_vertices([
+                &outputs[triangle.x as usize],
+                &outputs[triangle.y as usize],
+                &outputs[triangle.z as usize],
+            ]);
+            clip_cull_triangle(&triangle).for_each_triangle(|tri| triangles.push(*tri));
+        }
+        triangles
+    }
+
+    /// Shades and clips the mesh, then submits its triangles to the binner.
+    /// Nothing is drawn until the binner is flushed.
+    pub fn draw_mesh<'s, U, VS, FS, C>(
+        &self,
+        render_state: &RenderState,
+        uniforms: &'s U,
+        vertex_shader: &VS,
+        fragment_shader: &'s FS,
+        binner: &mut Binner<'s, C>,
+    ) where
+        U: Sync,
+        VS: VertexShader<U, V>,
+        FS: FragmentShader<U, C, Varyings = VS::Varyings>,
+        C: ColorFormat,
+    {
+        let triangles = self.process_vertices(uniforms, vertex_shader);
+        binner.submit(render_state, uniforms, fragment_shader, &triangles);
+    }
+
+    /// Transforms and clips the mesh, then submits its triangles to the binner to be drawn
+    /// into the depth attachment only. Only the positions written by the vertex shader matter.
+    pub fn draw_depth<'s, U, VS, C>(
+        &self,
+        render_state: &RenderState,
+        uniforms: &U,
+        vertex_shader: &VS,
+        binner: &mut Binner<'s, C>,
+    ) where
+        VS: VertexShader<U, V>,
+        VS::Varyings: 's,
+        C: ColorFormat,
+    {
+        let triangles = self.process_vertices(uniforms, vertex_shader);
+        binner.submit_depth(render_state, &triangles);
+    }
+
+    /// Rasterizes the mesh on the calling thread, writing straight into the target.
+    pub fn draw_mesh_immediate<U, VS, FS, C>(
+        &self,
+        render_state: &RenderState,
+        uniforms: &U,
+        vertex_shader: &VS,
+        fragment_shader: &FS,
+        target: &mut Framebuffer<C>,
+    ) where
+        VS: VertexShader<U, V>,
+        FS: FragmentShader<U, C, Varyings = VS::Varyings>,
+        C: ColorFormat,
+    {
+        for triangle in self.process_vertices(uniforms, vertex_shader) {
+            draw_triangle_clipped(&triangle, render_state, uniforms, fragment_shader, target);
         }
     }
 }
 
+#[derive(Debug, Copy, Clone)]
 pub struct BoundingBox2D {
     pub min: Vec2,
     pub max: Vec2,
@@ -0,0 +1,413 @@
+//! Imports glTF 2.0 files as a `Scene`: the node hierarchy of the default scene, every mesh
+//! with all of its primitives, their materials and the images those reference.
+
+use std::{collections::HashSet, error::Error, fmt, path::Path};
+
+use glam::{Quat, UVec3, UVec4, Vec2, Vec3, Vec4};
+use gltf::{
+    image::Format,
+    mesh::Mode,
+    texture::{MinFilter, WrappingMode},
+};
+use shared::{
+    material::{AlphaMode, Material, MaterialTexture},
+    mesh::{GltfVertex, Mesh},
+    scene::{Model, Node, Primitive, Scene},
+    texture::{ColorSpace, Filter, Sampler, Texture, WrapMode},
+    transform::Transform,
+};
+
+#[derive(Debug)]
+pub enum GltfError {
+    /// The file, its buffers or its images could not be read or parsed.
+    Import(gltf::Error),
+    /// An image uses a pixel format textures can't be created from.
+    UnsupportedImageFormat(Format),
+    /// A primitive's indices reference a vertex it doesn't have.
+    IndexOutOfRange {
+        mesh: usize,
+        primitive: usize,
+        index: u32,
+        vertex_count: usize,
+    },
+    /// A node is its own ancestor, the node hierarchy has to be a tree.
+    NodeCycle(usize),
+}
+
+impl fmt::Display for GltfError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GltfError::Import(error) => write!(f, "{}", error),
+            GltfError::UnsupportedImageFormat(format) => {
+                write!(f, "Unsupported image format: {:?}", format)
+            }
+            GltfError::IndexOutOfRange {
+                mesh,
+                primitive,
+                index,
+                vertex_count,
+            } => write!(
+                f,
+                "Primitive {} of mesh {} references vertex {}, but has {} vertices",
+                primitive, mesh, index, vertex_count
+            ),
+            GltfError::NodeCycle(node) => write!(f, "Node {} is its own ancestor", node),
+        }
+    }
+}
+
+impl Error for GltfError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            GltfError::Import(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<gltf::Error> for GltfError {
+    fn from(error: gltf::Error) -> Self {
+        GltfError::Import(error)
+    }
+}
+
+/// Loads a glTF file with its buffers and images. Point and line primitives are skipped.
+pub fn load_gltf_scene(path: &Path) -> Result<Scene, GltfError> {
+    let (document, buffers, images) = gltf::import(path)?;
+
+    // Base color and emissive images are sRGB encoded color, the others hold linear data
+    let color_images: HashSet<usize> = document
+        .materials()
+        .flat_map(|material| {
+            [
+                material
+                    .pbr_metallic_roughness()
+                    .base_color_texture()
+                    .map(|info| info.texture().source().index()),
+                material
+                    .emissive_texture()
+                    .map(|info| info.texture().source().index()),
+            ]
+        })
+        .flatten()
+        .collect();
+
+    let textures = images
+        .iter()
+        .enumerate()
+        .map(|(index, image)| {
+            let rgba = to_rgba8(image)?;
+            let color_space = match color_images.contains(&index) {
+                true => ColorSpace::Srgb,
+                false => ColorSpace::Linear,
+            };
+            Ok(
+                Texture::from_rgba8(image.width as usize, image.height as usize, &rgba)
+                    .to_linear(color_space),
+            )
+        })
+        .collect::<Result<_, GltfError>>()?;
+
+    let materials = document.materials().map(|m| material(&m)).collect();
+
+    let models = document
+        .meshes()
+        .map(|mesh| {
+            let mut primitives = Vec::new();
+            for primitive in mesh.primitives() {
+                if let Some(primitive_mesh) = primitive_mesh(&mesh, &primitive, &buffers)? {
+                    primitives.push(Primitive {
+                        mesh: primitive_mesh,
+                        material: primitive.material().index(),
+                    });
+                }
+            }
+            Ok(Model {
+                name: mesh.name().map(String::from),
+                primitives,
+            })
+        })
+        .collect::<Result<_, GltfError>>()?;
+
+    let nodes = document
+        .nodes()
+        .map(|node| {
+            let (translation, rotation, scale) = node.transform().decomposed();
+            Node {
+                name: node.name().map(String::from),
+                transform: Transform::new(
+                    Vec3::from(translation),
+                    Quat::from_array(rotation),
+                    Vec3::from(scale),
+                ),
+                model: node.mesh().map(|mesh| mesh.index()),
+                children: node.children().map(|child| child.index()).collect(),
+            }
+        })
+        .collect::<Vec<_>>();
+    if let Some(node) = node_in_cycle(&nodes) {
+        return Err(GltfError::NodeCycle(node));
+    }
+
+    // Without a scene to show, every node that is no other node's child is a root
+    let roots = match document
+        .default_scene()
+        .or_else(|| document.scenes().next())
+    {
+        Some(scene) => scene.nodes().map(|node| node.index()).collect(),
+        None => document
+            .nodes()
+            .map(|node| node.index())
+            .filter(|&index| {
+                document
+                    .nodes()
+                    .all(|parent| parent.children().all(|child| child.index() != index))
+            })
+            .collect(),
+    };
+
+    Ok(Scene {
+        nodes,
+        roots,
+        models,
+        materials,
+        textures,
+        ..Default::default()
+    })
+}
+
+fn material(material: &gltf::Material) -> Material {
+    let pbr = material.pbr_metallic_roughness();
+    let normal_texture = material.normal_texture();
+    let occlusion_texture = material.occlusion_texture();
+    Material {
+        name: material.name().map(String::from),
+        base_color_factor: Vec4::from(pbr.base_color_factor()),
+        base_color_texture: pbr
+            .base_color_texture()
+            .map(|info| material_texture(&info.texture(), info.tex_coord())),
+        metallic_factor: pbr.metallic_factor(),
+        roughness_factor: pbr.roughness_factor(),
+        metallic_roughness_texture: pbr
+            .metallic_roughness_texture()
+            .map(|info| material_texture(&info.texture(), info.tex_coord())),
+        normal_texture: normal_texture
+            .as_ref()
+            .map(|normal| material_texture(&normal.texture(), normal.tex_coord())),
+        normal_scale: normal_texture.as_ref().map_or(1.0, |normal| normal.scale()),
+        occlusion_texture: occlusion_texture
+            .as_ref()
+            .map(|occlusion| material_texture(&occlusion.texture(), occlusion.tex_coord())),
+        occlusion_strength: occlusion_texture
+            .as_ref()
+            .map_or(1.0, |occlusion| occlusion.strength()),
+        emissive_factor: Vec3::from(material.emissive_factor()),
+        emissive_texture: material
+            .emissive_texture()
+            .map(|info| material_texture(&info.texture(), info.tex_coord())),
+        alpha_mode: match material.alpha_mode() {
+            gltf::material::AlphaMode::Opaque => AlphaMode::Opaque,
+            gltf::material::AlphaMode::Mask => AlphaMode::Mask,
+            gltf::material::AlphaMode::Blend => AlphaMode::Blend,
+        },
+        alpha_cutoff: material.alpha_cutoff().unwrap_or(0.5),
+        double_sided: material.double_sided(),
+        ..Default::default()
+    }
+}
+
+fn material_texture(texture: &gltf::Texture, tex_coord: u32) -> MaterialTexture {
+    MaterialTexture {
+        texture: texture.source().index(),
+        sampler: sampler(&texture.sampler()),
+        tex_coord: tex_coord as usize,
+    }
+}
+
+fn sampler(sampler: &gltf::texture::Sampler) -> Sampler {
+    let wrap = |mode| match mode {
+        WrappingMode::Repeat => WrapMode::Repeat,
+        WrappingMode::MirroredRepeat => WrapMode::MirroredRepeat,
+        WrappingMode::ClampToEdge => WrapMode::ClampToEdge,
+    };
+    let filter = match sampler.min_filter() {
+        Some(MinFilter::Nearest) => Filter::Nearest,
+        Some(MinFilter::Linear)
+        | Some(MinFilter::NearestMipmapNearest)
+        | Some(MinFilter::LinearMipmapNearest) => Filter::Bilinear,
+        _ => Filter::Trilinear,
+    };
+    Sampler {
+        filter,
+        wrap_u: wrap(sampler.wrap_s()),
+        wrap_v: wrap(sampler.wrap_t()),
+        ..Default::default()
+    }
+}
+
+/// A node that is its own ancestor, if the hierarchy has a cycle.
+fn node_in_cycle(nodes: &[Node]) -> Option<usize> {
+    #[derive(Copy, Clone, PartialEq)]
+    enum Visit {
+        New,
+        /// On the path from the node the search started at.
+        Open,
+        Done,
+    }
+
+    let mut visits = vec![Visit::New; nodes.len()];
+    for start in 0..nodes.len() {
+        if visits[start] != Visit::New {
+            continue;
+        }
+        // Depth-first, with the next child to visit of every node on the path
+        visits[start] = Visit::Open;
+        let mut path = vec![(start, 0)];
+        while let Some((node, child)) = path.pop() {
+            let Some(&next) = nodes[node].children.get(child) else {
+                visits[node] = Visit::Done;
+                continue;
+            };
+            path.push((node, child + 1));
+            match visits[next] {
+                Visit::New => {
+                    visits[next] = Visit::Open;
+                    path.push((next, 0));
+                }
+                Visit::Open => return Some(next),
+                Visit::Done => (),
+            }
+        }
+    }
+    None
+}
+
+/// Reads the vertices and triangles of a primitive, missing normals and tangents are
+/// generated. Returns `None` for point and line primitives and primitives without positions.
+fn primitive_mesh(
+    mesh: &gltf::Mesh,
+    primitive: &gltf::Primitive,
+    buffers: &[gltf::buffer::Data],
+) -> Result<Option<Mesh<GltfVertex>>, GltfError> {
+    let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+
+    let Some(positions) = reader.read_positions() else {
+        return Ok(None);
+    };
+    let mut vertices: Vec<GltfVertex> = positions
+        .map(|p| GltfVertex {
+            position: Vec3::from(p).extend(1.0),
+            ..Default::default()
+        })
+        .collect();
+
+    let normals = reader.read_normals();
+    let has_normals = normals.is_some();
+    if let Some(normals) = normals {
+        for (v, n) in vertices.iter_mut().zip(normals) {
+            v.normal = Vec3::from(n);
+        }
+    }
+    let tangents = reader.read_tangents();
+    let has_tangents = tangents.is_some();
+    if let Some(tangents) = tangents {
+        for (v, t) in vertices.iter_mut().zip(tangents) {
+            v.tangent = Vec4::from(t);
+        }
+    }
+    let tex_coords = reader.read_tex_coords(0);
+    let has_tex_coords = tex_coords.is_some();
+    if let Some(tex_coords) = tex_coords {
+        for (v, tc) in vertices.iter_mut().zip(tex_coords.into_f32()) {
+            v.uv = Vec2::from(tc);
+        }
+    }
+    if let Some(tex_coords) = reader.read_tex_coords(1) {
+        for (v, tc) in vertices.iter_mut().zip(tex_coords.into_f32()) {
+            v.uv1 = Vec2::from(tc);
+        }
+    }
+    if let Some(colors) = reader.read_colors(0) {
+        for (v, c) in vertices.iter_mut().zip(colors.into_rgba_f32()) {
+            v.color = Vec4::from(c);
+        }
+    }
+    if let Some(joints) = reader.read_joints(0) {
+        for (v, j) in vertices.iter_mut().zip(joints.into_u16()) {
+            v.joints = UVec4::from(j.map(u32::from));
+        }
+    }
+    if let Some(weights) = reader.read_weights(0) {
+        for (v, w) in vertices.iter_mut().zip(weights.into_f32()) {
+            v.weights = Vec4::from(w);
+        }
+    }
+
+    let indices: Vec<u32> = match reader.read_indices() {
+        Some(indices) => indices.into_u32().collect(),
+        None => (0..vertices.len() as u32).collect(),
+    };
+    if let Some(&index) = indices
+        .iter()
+        .find(|&&index| index as usize >= vertices.len())
+    {
+        return Err(GltfError::IndexOutOfRange {
+            mesh: mesh.index(),
+            primitive: primitive.index(),
+            index,
+            vertex_count: vertices.len(),
+        });
+    }
+
+    let mut triangles: Vec<UVec3> = match primitive.mode() {
+        Mode::Triangles => indices
+            .chunks_exact(3)
+            .map(|tri| UVec3::new(tri[0], tri[1], tri[2]))
+            .collect(),
+        // Every other triangle of a strip is wound the other way around
+        Mode::TriangleStrip => indices
+            .windows(3)
+            .enumerate()
+            .map(|(i, tri)| match i % 2 {
+                0 => UVec3::new(tri[0], tri[1], tri[2]),
+                _ => UVec3::new(tri[0], tri[2], tri[1]),
+            })
+            .collect(),
+        Mode::TriangleFan => indices
+            .windows(2)
+            .skip(1)
+            .map(|pair| UVec3::new(indices[0], pair[0], pair[1]))
+            .collect(),
+        Mode::Points | Mode::Lines | Mode::LineLoop | Mode::LineStrip => return Ok(None),
+    };
+
+    let mut mesh = Mesh::new();
+    mesh.add_vertices(&mut triangles, &mut vertices);
+    if !has_normals {
+        mesh.generate_normals();
+    }
+    if !has_tangents && has_tex_coords {
+        mesh.generate_tangents();
+    }
+    Ok(Some(mesh))
+}
+
+/// Expands the decoded pixels of an image to RGBA, one byte per channel.
+fn to_rgba8(image: &gltf::image::Data) -> Result<Vec<u8>, GltfError> {
+    let pixels = &image.pixels;
+    let rgba = match image.format {
+        // Single channel images are treated as grayscale, two channels as grayscale with alpha
+        Format::R8 => pixels.iter().flat_map(|&r| [r, r, r, 255]).collect(),
+        Format::R8G8 => pixels
+            .chunks_exact(2)
+            .flat_map(|p| [p[0], p[0], p[0], p[1]])
+            .collect(),
+        Format::R8G8B8 => pixels
+            .chunks_exact(3)
+            .flat_map(|p| [p[0], p[1], p[2], 255])
+            .collect(),
+        Format::R8G8B8A8 => pixels.clone(),
+        format => return Err(GltfError::UnsupportedImageFormat(format)),
+    };
+    Ok(rgba)
+}
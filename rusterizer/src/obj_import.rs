@@ -0,0 +1,448 @@
+//! Imports Wavefront OBJ files and their MTL materials as a `Scene`.
+//!
+//! Every object and group becomes a model placed by its own root node, with a primitive per
+//! material used in it. Position, texture coordinate and normal indices are deduplicated
+//! into unified vertices, polygons are triangulated as fans and so have to be convex. Smooth
+//! normals are generated for primitives with faces that have none. Vertex colors given
+//! after a position, as in `v x y z r g b`, are kept.
+
+use std::{
+    collections::HashMap,
+    error::Error,
+    fmt, fs, io,
+    path::{Path, PathBuf},
+};
+
+use glam::{UVec3, Vec2, Vec3};
+use shared::{
+    material::{AlphaMode, Material, MaterialTexture},
+    mesh::{GltfVertex, Mesh},
+    scene::{Model, Node, Primitive, Scene},
+    texture::{ColorSpace, Sampler, Texture},
+    transform::Transform,
+};
+
+#[derive(Debug)]
+pub enum ObjError {
+    /// An OBJ or MTL file could not be read.
+    Io { path: PathBuf, error: io::Error },
+    /// A line of an OBJ or MTL file is malformed. Lines count from 1.
+    Parse {
+        path: PathBuf,
+        line: usize,
+        message: String,
+    },
+    /// A texture referenced by a material could not be loaded.
+    Texture {
+        path: PathBuf,
+        message: &'static str,
+    },
+}
+
+impl fmt::Display for ObjError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ObjError::Io { path, error } => write!(f, "{}: {}", path.display(), error),
+            ObjError::Parse {
+                path,
+                line,
+                message,
+            } => write!(f, "{}:{}: {}", path.display(), line, message),
+            ObjError::Texture { path, message } => write!(f, "{}: {}", path.display(), message),
+        }
+    }
+}
+
+impl Error for ObjError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ObjError::Io { error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
+
+/// Loads an OBJ file, with the MTL files and textures it references.
+pub fn load_obj_scene(path: &Path) -> Result<Scene, ObjError> {
+    let source = read(path)?;
+    let mut importer = ObjImporter::default();
+
+    for (number, line) in source.lines().enumerate() {
+        importer.parse_line(path, number + 1, line)?;
+    }
+    Ok(importer.finish())
+}
+
+fn read(path: &Path) -> Result<String, ObjError> {
+    fs::read_to_string(path).map_err(|error| ObjError::Io {
+        path: path.to_path_buf(),
+        error,
+    })
+}
+
+/// Primitive being filled with the faces of one material.
+struct PrimitiveBuilder {
+    material: Option<usize>,
+    mesh: Mesh<GltfVertex>,
+    /// Unified vertex of every position, texture coordinate and normal index combination.
+    vertices: HashMap<(usize, Option<usize>, Option<usize>), u32>,
+    /// Some faces have no normals, they are generated for the whole primitive.
+    missing_normals: bool,
+}
+
+#[derive(Default)]
+struct ModelBuilder {
+    name: Option<String>,
+    primitives: Vec<PrimitiveBuilder>,
+}
+
+#[derive(Default)]
+struct ObjImporter {
+    positions: Vec<Vec3>,
+    /// Vertex colors given after the positions, white for positions without one.
+    colors: Vec<Vec3>,
+    tex_coords: Vec<Vec2>,
+    normals: Vec<Vec3>,
+    model: ModelBuilder,
+    material: Option<usize>,
+    scene: Scene,
+    material_indices: HashMap<String, usize>,
+    texture_indices: HashMap<PathBuf, usize>,
+}
+
+impl ObjImporter {
+    fn parse_line(&mut self, path: &Path, number: usize, line: &str) -> Result<(), ObjError> {
+        let parse_error = |message: String| ObjError::Parse {
+            path: path.to_path_buf(),
+            line: number,
+            message,
+        };
+        let line = line.split('#').next().unwrap_or_default();
+        let mut tokens = line.split_whitespace();
+        let Some(keyword) = tokens.next() else {
+            return Ok(());
+        };
+
+        match keyword {
+            "v" => {
+                // x y z with an optional weight, which only free-form geometry uses, or
+                // x y z r g b with a vertex color
+                let v = floats(tokens, 3, 6).map_err(parse_error)?;
+                let color = match v.len() {
+                    5 => return Err(parse_error("Expected 3, 4 or 6 numbers, found 5".into())),
+                    6 => Vec3::new(v[3], v[4], v[5]),
+                    _ => Vec3::ONE,
+                };
+                self.positions.push(Vec3::new(v[0], v[1], v[2]));
+                self.colors.push(color);
+            }
+            "vt" => {
+                // OBJ puts v = 0 at the bottom of the image, textures have it at the top
+                let vt = floats(tokens, 1, 3).map_err(parse_error)?;
+                let v = vt.get(1).copied().unwrap_or(0.0);
+                self.tex_coords.push(Vec2::new(vt[0], 1.0 - v));
+            }
+            "vn" => {
+                let vn = floats(tokens, 3, 3).map_err(parse_error)?;
+                self.normals.push(Vec3::new(vn[0], vn[1], vn[2]));
+            }
+            "f" => self.face(tokens).map_err(parse_error)?,
+            "o" | "g" => {
+                let name = tokens.collect::<Vec<_>>().join(" ");
+                self.start_model((!name.is_empty()).then_some(name));
+            }
+            "usemtl" => {
+                let name = tokens.collect::<Vec<_>>().join(" ");
+                let material = self.material_indices.get(&name);
+                self.material = Some(
+                    *material.ok_or_else(|| parse_error(format!("Unknown material {:?}", name)))?,
+                );
+            }
+            "mtllib" => {
+                let directory = path.parent().unwrap_or(Path::new(""));
+                for file in tokens {
+                    self.load_mtl(&directory.join(file))?;
+                }
+            }
+            // Smoothing groups, lines, points and free-form geometry are skipped
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn face<'a>(&mut self, tokens: impl Iterator<Item = &'a str>) -> Result<(), String> {
+        let corners = tokens
+            .map(|token| self.corner(token))
+            .collect::<Result<Vec<_>, _>>()?;
+        if corners.len() < 3 {
+            return Err(format!("Face with {} vertices", corners.len()));
+        }
+
+        let material = self.material;
+        let primitive = match self
+            .model
+            .primitives
+            .iter()
+            .position(|primitive| primitive.material == material)
+        {
+            Some(index) => &mut self.model.primitives[index],
+            None => {
+                self.model.primitives.push(PrimitiveBuilder {
+                    material,
+                    mesh: Mesh::new(),
+                    vertices: HashMap::new(),
+                    missing_normals: false,
+                });
+                self.model.primitives.last_mut().unwrap()
+            }
+        };
+
+        primitive.missing_normals |= corners.iter().any(|corner| corner.2.is_none());
+        let indices: Vec<u32> = corners
+            .iter()
+            .map(|&(position, tex_coord, normal)| {
+                *primitive
+                    .vertices
+                    .entry((position, tex_coord, normal))
+                    .or_insert_with(|| {
+                        let mut vertex = GltfVertex {
+                            position: self.positions[position].extend(1.0),
+                            color: self.colors[position].extend(1.0),
+                            ..Default::default()
+                        };
+                        if let Some(tex_coord) = tex_coord {
+                            vertex.uv = self.tex_coords[tex_coord];
+                        }
+                        if let Some(normal) = normal {
+                            vertex.normal = self.normals[normal];
+                        }
+                        primitive.mesh.vertices.push(vertex);
+                        primitive.mesh.vertices.len() as u32 - 1
+                    })
+            })
+            .collect();
+
+        for i in 1..indices.len() - 1 {
+            primitive
+                .mesh
+                .triangles
+                .push(UVec3::new(indices[0], indices[i], indices[i + 1]));
+        }
+        Ok(())
+    }
+
+    /// Parses a face vertex, `v`, `v/vt`, `v//vn` or `v/vt/vn`, to indices into the
+    /// attribute lists.
+    fn corner(&self, token: &str) -> Result<(usize, Option<usize>, Option<usize>), String> {
+        let mut parts = token.split('/');
+        let position = resolve(parts.next().unwrap_or_default(), self.positions.len())?;
+        let tex_coord = match parts.next() {
+            Some(index) if !index.is_empty() => Some(resolve(index, self.tex_coords.len())?),
+            _ => None,
+        };
+        let normal = match parts.next() {
+            Some(index) => Some(resolve(index, self.normals.len())?),
+            None => None,
+        };
+        if parts.next().is_some() {
+            return Err(format!("Invalid face vertex {:?}", token));
+        }
+        Ok((position, tex_coord, normal))
+    }
+
+    /// Finishes the current model, unless nothing was added to it yet.
+    fn start_model(&mut self, name: Option<String>) {
+        if self.model.primitives.is_empty() {
+            self.model.name = name;
+            return;
+        }
+        let model = std::mem::replace(
+            &mut self.model,
+            ModelBuilder {
+                name,
+                primitives: Vec::new(),
+            },
+        );
+        self.scene.roots.push(self.scene.nodes.len());
+        self.scene.nodes.push(Node {
+            name: model.name.clone(),
+            transform: Transform::IDENTITY,
+            model: Some(self.scene.models.len()),
+            children: Vec::new(),
+        });
+        self.scene.models.push(Model {
+            name: model.name,
+            primitives: model
+                .primitives
+                .into_iter()
+                .map(|mut primitive| {
+                    if primitive.missing_normals {
+                        primitive.mesh.generate_normals();
+                    }
+                    Primitive {
+                        mesh: primitive.mesh,
+                        material: primitive.material,
+                    }
+                })
+                .collect(),
+        });
+    }
+
+    fn finish(mut self) -> Scene {
+        self.start_model(None);
+        self.scene
+    }
+
+    fn load_mtl(&mut self, path: &Path) -> Result<(), ObjError> {
+        let source = read(path)?;
+        let mut material: Option<Material> = None;
+
+        for (number, line) in source.lines().enumerate() {
+            let parse_error = |message: String| ObjError::Parse {
+                path: path.to_path_buf(),
+                line: number + 1,
+                message,
+            };
+            let line = line.split('#').next().unwrap_or_default();
+            let mut tokens = line.split_whitespace();
+            let Some(keyword) = tokens.next() else {
+                continue;
+            };
+
+            if keyword == "newmtl" {
+                self.add_material(material.take());
+                material = Some(Material {
+                    name: Some(tokens.collect::<Vec<_>>().join(" ")),
+                    metallic_factor: 0.0,
+                    ..Default::default()
+                });
+                continue;
+            }
+            let Some(material) = material.as_mut() else {
+                return Err(parse_error(format!("{:?} before newmtl", keyword)));
+            };
+
+            match keyword {
+                "Kd" => {
+                    let kd = floats(tokens, 3, 3).map_err(parse_error)?;
+                    let alpha = material.base_color_factor.w;
+                    material.base_color_factor = Vec3::new(kd[0], kd[1], kd[2]).extend(alpha);
+                }
+                "Ke" => {
+                    let ke = floats(tokens, 3, 3).map_err(parse_error)?;
+                    material.emissive_factor = Vec3::new(ke[0], ke[1], ke[2]);
+                }
+                "d" | "Tr" => {
+                    let value = floats(tokens, 1, 1).map_err(parse_error)?[0];
+                    let alpha = if keyword == "d" { value } else { 1.0 - value };
+                    material.base_color_factor.w = alpha;
+                    material.alpha_mode = if alpha < 1.0 {
+                        AlphaMode::Blend
+                    } else {
+                        AlphaMode::Opaque
+                    };
+                }
+                // Specular exponent, mapped to the roughness giving a similar highlight
+                "Ns" => {
+                    let ns = floats(tokens, 1, 1).map_err(parse_error)?[0];
+                    material.roughness_factor = (2.0 / (ns.max(0.0) + 2.0)).sqrt();
+                }
+                "Pr" => material.roughness_factor = floats(tokens, 1, 1).map_err(parse_error)?[0],
+                "Pm" => material.metallic_factor = floats(tokens, 1, 1).map_err(parse_error)?[0],
+                "map_Kd" | "map_Ke" => {
+                    // Options come before the file name, which is the last token
+                    let file = tokens
+                        .last()
+                        .ok_or_else(|| parse_error(format!("{} without a file", keyword)))?;
+                    let directory = path.parent().unwrap_or(Path::new(""));
+                    let texture = self.load_texture(&directory.join(file))?;
+                    let texture = Some(MaterialTexture {
+                        texture,
+                        sampler: Sampler::default(),
+                        tex_coord: 0,
+                    });
+                    match keyword {
+                        "map_Kd" => material.base_color_texture = texture,
+                        _ => {
+                            material.emissive_texture = texture;
+                            if material.emissive_factor == Vec3::ZERO {
+                                material.emissive_factor = Vec3::ONE;
+                            }
+                        }
+                    }
+                }
+                // Other colors, illumination models and maps have no equivalent
+                _ => {}
+            }
+        }
+        self.add_material(material);
+        Ok(())
+    }
+
+    fn add_material(&mut self, material: Option<Material>) {
+        if let Some(material) = material {
+            let name = material.name.clone().unwrap_or_default();
+            self.material_indices
+                .insert(name, self.scene.materials.len());
+            self.scene.materials.push(material);
+        }
+    }
+
+    fn load_texture(&mut self, path: &Path) -> Result<usize, ObjError> {
+        if let Some(&index) = self.texture_indices.get(path) {
+            return Ok(index);
+        }
+        let texture = Texture::load(path).map_err(|message| ObjError::Texture {
+            path: path.to_path_buf(),
+            message,
+        })?;
+        // Only color maps are loaded
+        self.scene
+            .textures
+            .push(texture.to_linear(ColorSpace::Srgb));
+        self.texture_indices
+            .insert(path.to_path_buf(), self.scene.textures.len() - 1);
+        Ok(self.scene.textures.len() - 1)
+    }
+}
+
+/// Parses between `min` and `max` numbers.
+fn floats<'a>(
+    tokens: impl Iterator<Item = &'a str>,
+    min: usize,
+    max: usize,
+) -> Result<Vec<f32>, String> {
+    let values = tokens
+        .map(|token| {
+            token
+                .parse::<f32>()
+                .map_err(|_| format!("Invalid number {:?}", token))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    if values.len() < min || values.len() > max {
+        return Err(format!(
+            "Expected {} to {} numbers, found {}",
+            min,
+            max,
+            values.len()
+        ));
+    }
+    Ok(values)
+}
+
+/// Resolves a 1-based index, or a negative one counting back from the end, into a list of
+/// `count` elements.
+fn resolve(index: &str, count: usize) -> Result<usize, String> {
+    let value: i64 = index
+        .parse()
+        .map_err(|_| format!("Invalid index {:?}", index))?;
+    let resolved = match value {
+        1.. => value - 1,
+        ..=-1 => count as i64 + value,
+        0 => -1,
+    };
+    if resolved < 0 || resolved >= count as i64 {
+        return Err(format!("Index {} out of range", value));
+    }
+    Ok(resolved as usize)
+}
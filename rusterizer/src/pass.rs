@@ -0,0 +1,92 @@
+//! Render passes: the draw calls into one framebuffer, shaded together when the pass finishes.
+
+use glam::Vec2;
+use shared::{
+    framebuffer::Framebuffer,
+    mesh::{Mesh, MeshVertex},
+};
+
+use crate::{
+    binner::Binner,
+    color::ColorFormat,
+    geometry::{RenderMesh, RenderState},
+    shader::{FragmentShader, VertexShader},
+};
+
+/// Draw calls into one target, binned as they are made and shaded by `finish`.
+///
+/// A pass borrows its target until it is finished, so passes run in the order they are
+/// finished. Only then can a later pass sample the target, by binding its
+/// `Framebuffer::color_texture` or `Framebuffer::depth_texture` in its uniforms.
+///
+/// The fragment shaders of the pass write color in the target's format `C`.
+pub struct RenderPass<'a, C = u32> {
+    target: &'a mut Framebuffer<C>,
+    binner: Binner<'a, C>,
+}
+
+impl<'a, C: ColorFormat> RenderPass<'a, C> {
+    /// Starts a pass drawing over what the target already holds.
+    pub fn load(target: &'a mut Framebuffer<C>) -> Self {
+        let viewport = Vec2::new(target.width as f32, target.height as f32);
+        Self {
+            target,
+            binner: Binner::new(viewport),
+        }
+    }
+
+    /// Starts a pass on a target cleared to `color` and to the far plane.
+    pub fn clear(target: &'a mut Framebuffer<C>, color: C) -> Self {
+        target.clear_color(color);
+        target.clear_depth();
+        Self::load(target)
+    }
+
+    /// Shades the mesh with the given shaders. Uniforms have to outlive the pass.
+    pub fn draw<U, V, VS, FS>(
+        &mut self,
+        mesh: &Mesh<V>,
+        render_state: &RenderState,
+        uniforms: &'a U,
+        vertex_shader: &VS,
+        fragment_shader: &'a FS,
+    ) where
+        U: Sync,
+        V: MeshVertex,
+        VS: VertexShader<U, V>,
+        FS: FragmentShader<U, C, Varyings = VS::Varyings>,
+    {
+        RenderMesh::from_mesh(mesh).draw_mesh(
+            render_state,
+            uniforms,
+            vertex_shader,
+            fragment_shader,
+            &mut self.binner,
+        );
+    }
+
+    /// Draws the depth of the mesh only, without running a fragment shader.
+    pub fn draw_depth<U, V, VS>(
+        &mut self,
+        mesh: &Mesh<V>,
+        render_state: &RenderState,
+        uniforms: &U,
+        vertex_shader: &VS,
+    ) where
+        V: MeshVertex,
+        VS: VertexShader<U, V>,
+        VS::Varyings: 'a,
+    {
+        RenderMesh::from_mesh(mesh).draw_depth(
+            render_state,
+            uniforms,
+            vertex_shader,
+            &mut self.binner,
+        );
+    }
+
+    /// Shades everything drawn into the target, which later passes can then sample.
+    pub fn finish(self) {
+        self.binner.flush(self.target);
+    }
+}
@@ -0,0 +1,265 @@
+//! Physically based shading of glTF metallic-roughness materials, with a Cook-Torrance
+//! specular term.
+
+use std::f32::consts::PI;
+
+use glam::{Mat3, Vec2, Vec3, Vec4};
+use shared::{
+    camera::Camera,
+    framebuffer::Framebuffer,
+    light::Light,
+    material::{AlphaMode, Material, MaterialTexture},
+    mesh::{GltfVertex, Mesh},
+    scene::Scene,
+    texture::Texture,
+};
+
+use crate::{
+    color::ColorFormat,
+    geometry::{BlendMode, CullMode, RenderState},
+    impl_varyings,
+    pass::RenderPass,
+    shader::{Fragment, FragmentShader, Transforms, VertexOutput, VertexShader},
+    shadow::ShadowMap,
+    utils::perturb_normal,
+};
+
+pub struct PbrUniforms<'a> {
+    pub transforms: Transforms,
+    pub material: &'a Material,
+    /// Textures the material's texture indices refer to.
+    pub textures: &'a [Texture<Vec4>],
+    pub camera_position: Vec3,
+    pub lights: &'a [Light],
+    /// Light reaching every surface from all directions, scaled by the occlusion texture.
+    pub ambient_light: Vec3,
+    /// Shadows of one of the lights, applied if the material receives shadows.
+    pub shadow: Option<&'a ShadowMap>,
+}
+
+impl AsRef<Transforms> for PbrUniforms<'_> {
+    fn as_ref(&self) -> &Transforms {
+        &self.transforms
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct PbrVaryings {
+    pub world_position: Vec3,
+    pub normal: Vec3,
+    /// World space tangent, with the bitangent's sign in w.
+    pub tangent: Vec4,
+    pub uv: Vec2,
+    pub uv1: Vec2,
+}
+
+impl_varyings!(PbrVaryings {
+    world_position,
+    normal,
+    tangent,
+    uv,
+    uv1
+});
+
+/// Shades glTF vertices with their material: a Lambertian diffuse term and a Cook-Torrance
+/// specular term with the GGX distribution, Smith-Schlick geometry and Schlick's Fresnel
+/// approximation. Surfaces are lit with their interpolated vertex normals, perturbed by the
+/// normal texture if the material has one. Materials receiving shadows are darkened by the
+/// shadow map, if there is one.
+///
+/// Light is unbounded on HDR targets, 8-bit targets receive it clamped and sRGB encoded.
+pub struct PbrShader;
+
+impl VertexShader<PbrUniforms<'_>, GltfVertex> for PbrShader {
+    type Varyings = PbrVaryings;
+
+    fn vertex(&self, uniforms: &PbrUniforms, vertex: &GltfVertex) -> VertexOutput<PbrVaryings> {
+        let model = uniforms.transforms.model;
+        let normal_matrix = Mat3::from_mat4(model).inverse().transpose();
+        VertexOutput {
+            position: uniforms.transforms.model_view_projection() * vertex.position,
+            varyings: PbrVaryings {
+                world_position: (model * vertex.position).truncate(),
+                normal: normal_matrix * vertex.normal,
+                tangent: (Mat3::from_mat4(model) * vertex.tangent.truncate())
+                    .extend(vertex.tangent.w),
+                uv: vertex.uv,
+                uv1: vertex.uv1,
+            },
+        }
+    }
+}
+
+impl<C: ColorFormat> FragmentShader<PbrUniforms<'_>, C> for PbrShader {
+    type Varyings = PbrVaryings;
+
+    fn fragment(&self, uniforms: &PbrUniforms, fragment: &Fragment<PbrVaryings>) -> Option<C> {
+        let material = uniforms.material;
+        let sample = |texture: &Option<MaterialTexture>| {
+            texture.map(|texture| sample_texture(uniforms.textures, &texture, fragment))
+        };
+
+        let mut base_color = material.base_color_factor;
+        if let Some(texel) = sample(&material.base_color_texture) {
+            base_color *= texel;
+        }
+        if material.alpha_mode == AlphaMode::Mask && base_color.w < material.alpha_cutoff {
+            return None;
+        }
+
+        let mut metallic = material.metallic_factor;
+        let mut roughness = material.roughness_factor;
+        if let Some(texel) = sample(&material.metallic_roughness_texture) {
+            roughness *= texel.y;
+            metallic *= texel.z;
+        }
+        // Fully smooth surfaces have an infinitely thin specular peak
+        let roughness = roughness.clamp(0.04, 1.0);
+
+        let occlusion = sample(&material.occlusion_texture).map_or(1.0, |texel| {
+            1.0 + material.occlusion_strength * (texel.x - 1.0)
+        });
+
+        let mut emissive = material.emissive_factor;
+        if let Some(texel) = sample(&material.emissive_texture) {
+            emissive *= texel.truncate();
+        }
+
+        let varyings = &fragment.varyings;
+        let facing = if fragment.front_facing { 1.0 } else { -1.0 };
+        let shadow = uniforms.shadow.filter(|_| material.receive_shadows);
+        let n = match sample(&material.normal_texture) {
+            Some(texel) => perturb_normal(
+                varyings.normal,
+                varyings.tangent,
+                texel.truncate(),
+                material.normal_scale,
+            ),
+            None => varyings.normal.normalize_or_zero(),
+        } * facing;
+        let v = (uniforms.camera_position - varyings.world_position).normalize_or_zero();
+        let n_dot_v = n.dot(v).max(1e-4);
+
+        // Dielectrics reflect about 4% at normal incidence, metals their base color
+        let albedo = base_color.truncate();
+        let f0 = Vec3::splat(0.04).lerp(albedo, metallic);
+        let alpha = roughness * roughness;
+        let alpha2 = alpha * alpha;
+        let k = (roughness + 1.0).powi(2) / 8.0;
+
+        let mut color = uniforms.ambient_light * albedo * occlusion + emissive;
+        for (index, light) in uniforms.lights.iter().enumerate() {
+            let (l, mut radiance) = light.illuminate(varyings.world_position);
+            let h = (v + l).normalize_or_zero();
+            let n_dot_l = n.dot(l).max(0.0);
+            if n_dot_l == 0.0 {
+                continue;
+            }
+            if let Some(shadow) = shadow.filter(|shadow| shadow.light == index) {
+                // The geometric normal, the normal texture's detail would move the shadow
+                radiance *= shadow.visibility(varyings.world_position, varyings.normal * facing);
+            }
+            let n_dot_h = n.dot(h).max(0.0);
+            let v_dot_h = v.dot(h).max(0.0);
+
+            let fresnel = f0 + (Vec3::ONE - f0) * (1.0 - v_dot_h).powi(5);
+            let d = alpha2 / (PI * (n_dot_h * n_dot_h * (alpha2 - 1.0) + 1.0).powi(2));
+            let g = (n_dot_l / (n_dot_l * (1.0 - k) + k)) * (n_dot_v / (n_dot_v * (1.0 - k) + k));
+
+            let specular = fresnel * d * g / (4.0 * n_dot_l * n_dot_v).max(1e-4);
+            let diffuse = (Vec3::ONE - fresnel) * (1.0 - metallic) * albedo / PI;
+            color += (diffuse + specular) * radiance * n_dot_l;
+        }
+
+        let alpha = match material.alpha_mode {
+            AlphaMode::Blend => base_color.w,
+            _ => 1.0,
+        };
+        Some(C::from_linear(color.extend(alpha)))
+    }
+}
+
+/// Samples a material texture with the texture coordinate set it names, as linear RGBA.
+fn sample_texture(
+    textures: &[Texture<Vec4>],
+    texture: &MaterialTexture,
+    fragment: &Fragment<PbrVaryings>,
+) -> Vec4 {
+    let (uv, ddx, ddy) = match texture.tex_coord {
+        0 => (fragment.varyings.uv, fragment.ddx.uv, fragment.ddy.uv),
+        _ => (fragment.varyings.uv1, fragment.ddx.uv1, fragment.ddy.uv1),
+    };
+    textures[texture.texture].sample(&texture.sampler, uv, ddx, ddy)
+}
+
+/// Draws every model instance of the scene with its materials, lit by the scene's lights.
+/// The shadow map, rendered beforehand, darkens its light on materials receiving shadows.
+///
+/// Blended materials are drawn after the opaque ones, sorted back to front by the center of
+/// their primitives. They are depth tested but don't write depth, so translucent surfaces
+/// crossing each other or overlapping within one primitive can be blended out of order.
+pub fn draw_scene<C: ColorFormat>(
+    scene: &Scene,
+    camera: &Camera,
+    shadow: Option<&ShadowMap>,
+    target: &mut Framebuffer<C>,
+) {
+    let view = camera.view();
+    // The pass refers to the uniforms until it is finished
+    let mut draws: Vec<_> = scene
+        .instances()
+        .into_iter()
+        .flat_map(|(world, model)| {
+            model.primitives.iter().map(move |primitive| {
+                let material = scene.material(primitive);
+                let uniforms = PbrUniforms {
+                    transforms: Transforms::from_model(world, camera),
+                    material,
+                    textures: &scene.textures,
+                    camera_position: camera.transform.translation,
+                    lights: &scene.lights,
+                    ambient_light: scene.ambient_light,
+                    shadow,
+                };
+                let mut render_state = RenderState::new();
+                if material.double_sided {
+                    render_state.cull_mode = CullMode::None;
+                }
+                if material.alpha_mode == AlphaMode::Blend {
+                    render_state.blend_mode = BlendMode::Alpha;
+                    render_state.depth_write = false;
+                }
+                // View space looks down -z, so the farthest primitive has the lowest depth
+                let depth = (view * world).transform_point3(center(&primitive.mesh)).z;
+                (&primitive.mesh, uniforms, render_state, depth)
+            })
+        })
+        .collect();
+
+    // Opaque draws keep their order, blended ones follow from back to front
+    draws.sort_by(|a, b| {
+        let blended = |render_state: &RenderState| render_state.blend_mode == BlendMode::Alpha;
+        match (blended(&a.2), blended(&b.2)) {
+            (true, true) => a.3.total_cmp(&b.3),
+            (a, b) => a.cmp(&b),
+        }
+    });
+
+    let mut pass = RenderPass::load(target);
+    for (mesh, uniforms, render_state, _) in &draws {
+        pass.draw(mesh, render_state, uniforms, &PbrShader, &PbrShader);
+    }
+    pass.finish();
+}
+
+/// Center of the mesh's bounding box, in object space.
+fn center(mesh: &Mesh<GltfVertex>) -> Vec3 {
+    let (min, max) = mesh.vertices.iter().fold(
+        (Vec3::splat(f32::INFINITY), Vec3::splat(f32::NEG_INFINITY)),
+        |(min, max), vertex| {
+            let position = vertex.position.truncate();
+            (min.min(position), max.max(position))
+        },
+    );
+    (min + max) * 0.5
+}
@@ -0,0 +1,487 @@
+//! Post-processing: full-screen effects applied in order to a rendered frame, each reading the
+//! color the previous one wrote.
+//!
+//! Effects work on the color values of the target: sRGB encoded in 0 to 1 for 8-bit targets and
+//! unbounded linear light for HDR targets, which are tone mapped afterwards.
+
+use std::{f32::consts::PI, thread};
+
+use glam::{Vec2, Vec3};
+use shared::{
+    framebuffer::Framebuffer,
+    texture::{Filter, Sampler, Texture, TextureView, WrapMode},
+};
+
+use crate::{color::ColorFormat, shader::Fragment};
+
+/// What an effect reads. Both views have the size of the frame.
+pub struct PostInput<'a, C = u32> {
+    /// Color written by the previous effect, or the rendered frame for the first one.
+    pub color: TextureView<'a, C>,
+    /// Depth of the rendered frame.
+    pub depth: TextureView<'a, f32>,
+}
+
+/// A full-screen effect of a `PostChain` on targets of color format `C`.
+pub trait PostEffect<C = u32>: Sync {
+    /// Writes every pixel of `output`, a single sampled framebuffer the size of the input.
+    /// Intermediate results go into `scratch`, which the chain keeps between frames.
+    fn apply(&self, input: &PostInput<C>, output: &mut Framebuffer<C>, scratch: &mut Scratch<C>);
+}
+
+/// Framebuffers effects draw intermediate results into, shared by the effects of a chain.
+pub struct Scratch<C = u32> {
+    framebuffers: Vec<Framebuffer<C>>,
+}
+
+impl<C: ColorFormat> Scratch<C> {
+    /// The first `N` scratch framebuffers, single sampled and `width` x `height`. They are
+    /// only reallocated when their size changes, so they hold what was last drawn into them.
+    pub fn framebuffers<const N: usize>(
+        &mut self,
+        width: usize,
+        height: usize,
+    ) -> &mut [Framebuffer<C>; N] {
+        if self.framebuffers.len() < N {
+            self.framebuffers
+                .resize_with(N, || Framebuffer::new(width, height));
+        }
+        for framebuffer in &mut self.framebuffers[..N] {
+            if (framebuffer.width, framebuffer.height) != (width, height) {
+                *framebuffer = Framebuffer::new(width, height);
+            }
+        }
+        (&mut self.framebuffers[..N]).try_into().unwrap()
+    }
+}
+
+/// Effects applied one after the other to the color of a rendered frame.
+///
+/// The chain keeps the framebuffers the effects read and write, so applying it again to a frame
+/// of the same size allocates none.
+pub struct PostChain<C = u32> {
+    pub effects: Vec<Box<dyn PostEffect<C>>>,
+    /// Single sampled copy of multisampled frames.
+    resolved: Framebuffer<C>,
+    /// Effects take turns writing into one buffer while reading the other.
+    buffers: [Framebuffer<C>; 2],
+    scratch: Scratch<C>,
+}
+
+impl<C: ColorFormat> Default for PostChain<C> {
+    fn default() -> Self {
+        Self {
+            effects: Vec::new(),
+            resolved: Framebuffer::new(0, 0),
+            buffers: [Framebuffer::new(0, 0), Framebuffer::new(0, 0)],
+            scratch: Scratch {
+                framebuffers: Vec::new(),
+            },
+        }
+    }
+}
+
+impl<C: ColorFormat> PostChain<C> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, effect: impl PostEffect<C> + 'static) {
+        self.effects.push(Box::new(effect));
+    }
+
+    /// Runs the effects over the target's color and replaces it with the result. Multisampled
+    /// targets are resolved first, every sample of a pixel receives the result, and the
+    /// effects read the depth of each pixel's first sample.
+    pub fn apply(&mut self, target: &mut Framebuffer<C>) {
+        let Some(last) = self.effects.len().checked_sub(1) else {
+            return;
+        };
+        let (width, height) = (target.width, target.height);
+        if (self.resolved.width, self.resolved.height) != (width, height) {
+            self.resolved = Framebuffer::new(width, height);
+            self.buffers = [
+                Framebuffer::new(width, height),
+                Framebuffer::new(width, height),
+            ];
+        }
+
+        if target.samples > 1 {
+            target.resolve_into(&mut self.resolved.color);
+            for (depth, samples) in self
+                .resolved
+                .depth
+                .iter_mut()
+                .zip(target.depth.chunks_exact(target.samples))
+            {
+                *depth = samples[0];
+            }
+        }
+        let frame = match target.samples {
+            1 => &*target,
+            _ => &self.resolved,
+        };
+        let depth = frame.depth_texture();
+
+        let [even, odd] = &mut self.buffers;
+        for (index, effect) in self.effects.iter().enumerate() {
+            let (previous, output) = match index % 2 {
+                0 => (&*odd, &mut *even),
+                _ => (&*even, &mut *odd),
+            };
+            let color = match index {
+                0 => frame.color_texture(),
+                _ => previous.color_texture(),
+            };
+            effect.apply(&PostInput { color, depth }, output, &mut self.scratch);
+        }
+
+        let result = &self.buffers[last % 2].color;
+        if target.samples == 1 {
+            target.color.copy_from_slice(result);
+        } else {
+            for (pixel, &color) in result.iter().enumerate() {
+                let first = pixel * target.samples;
+                target.color[first..first + target.samples].fill(color);
+            }
+        }
+    }
+}
+
+/// Shades every pixel of the target with `shader`, which receives the pixel's texture
+/// coordinates, 0 to 1 across the target, as its varyings. Its `position` is the pixel center.
+/// Every sample of a pixel receives its color, depth is left as it is.
+///
+/// There is no geometry to rasterize, so the rows are split into one band per worker thread
+/// and shaded directly.
+pub fn draw_fullscreen<C, F>(target: &mut Framebuffer<C>, shader: F)
+where
+    C: ColorFormat,
+    F: Fn(&Fragment<Vec2>) -> C + Sync,
+{
+    if target.color.is_empty() {
+        return;
+    }
+    let (width, samples) = (target.width, target.samples);
+    let size = Vec2::new(target.width as f32, target.height as f32);
+    let workers = thread::available_parallelism()
+        .map(|count| count.get())
+        .unwrap_or(1);
+    let band_rows = target.height.div_ceil(workers);
+    let shader = &shader;
+
+    thread::scope(|scope| {
+        for (band, color) in target
+            .color
+            .chunks_mut(band_rows * width * samples)
+            .enumerate()
+        {
+            scope.spawn(move || {
+                for (index, pixel) in color.chunks_exact_mut(samples).enumerate() {
+                    let x = index % width;
+                    let y = band * band_rows + index / width;
+                    let position = Vec2::new(x as f32, y as f32) + 0.5;
+                    let fragment = Fragment {
+                        varyings: position / size,
+                        ddx: Vec2::new(1.0 / size.x, 0.0),
+                        ddy: Vec2::new(0.0, 1.0 / size.y),
+                        position,
+                        depth: 0.0,
+                        front_facing: true,
+                    };
+                    pixel.fill(shader(&fragment));
+                }
+            });
+        }
+    });
+}
+
+/// Color channels as stored, without alpha.
+fn rgb<C: ColorFormat>(color: C) -> Vec3 {
+    color.to_vec4().truncate()
+}
+
+/// Opaque color of channels as stored, clamped to 0 to 1 for 8-bit color.
+fn from_rgb<C: ColorFormat>(rgb: Vec3) -> C {
+    C::from_vec4(rgb.extend(1.0))
+}
+
+fn luminance(rgb: Vec3) -> f32 {
+    rgb.dot(Vec3::new(0.2126, 0.7152, 0.0722))
+}
+
+fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+    let t = ((x - edge0) / (edge1 - edge0).max(f32::EPSILON)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+const BILINEAR: Sampler = Sampler {
+    filter: Filter::Bilinear,
+    wrap_u: WrapMode::ClampToEdge,
+    wrap_v: WrapMode::ClampToEdge,
+    border_color: 0,
+    max_anisotropy: 1,
+};
+
+/// Sample of a view at `uv`, without mips to choose from.
+fn bilinear<C: ColorFormat>(view: &TextureView<C>, uv: Vec2) -> Vec3 {
+    rgb(view.sample(&BILINEAR, uv, Vec2::ZERO, Vec2::ZERO))
+}
+
+/// Integer coordinates of the pixel a fragment shades.
+fn pixel(fragment: &Fragment<Vec2>) -> (i64, i64) {
+    (fragment.position.x as i64, fragment.position.y as i64)
+}
+
+/// Makes bright parts of the frame glow. Everything above the threshold is blurred at half
+/// resolution and added back to the frame.
+#[derive(Debug, Copy, Clone)]
+pub struct Bloom {
+    /// Luminance above which pixels glow, either in 0 to 1 or in linear light for HDR targets.
+    pub threshold: f32,
+    /// Strength of the glow added back.
+    pub intensity: f32,
+    /// Standard deviation of the blur in pixels of the half resolution glow.
+    pub sigma: f32,
+}
+
+impl Default for Bloom {
+    fn default() -> Self {
+        Self {
+            threshold: 0.7,
+            intensity: 0.8,
+            sigma: 4.0,
+        }
+    }
+}
+
+impl Bloom {
+    /// One side of a normalized Gaussian kernel, from the center tap outwards.
+    fn weights(&self) -> Vec<f32> {
+        let sigma = self.sigma.max(0.1);
+        let radius = (sigma * 3.0).ceil() as usize;
+        let weights: Vec<f32> = (0..=radius)
+            .map(|i| (-((i * i) as f32) / (2.0 * sigma * sigma)).exp())
+            .collect();
+        let sum = weights[0] + 2.0 * weights[1..].iter().sum::<f32>();
+        weights.iter().map(|weight| weight / sum).collect()
+    }
+}
+
+/// Blurs `source` along one axis into `target`.
+fn blur<C: ColorFormat>(
+    source: TextureView<C>,
+    target: &mut Framebuffer<C>,
+    weights: &[f32],
+    axis: (i64, i64),
+) {
+    draw_fullscreen(target, |fragment| {
+        let (x, y) = pixel(fragment);
+        let mut sum = rgb(source.texel(x, y)) * weights[0];
+        for (i, &weight) in weights.iter().enumerate().skip(1) {
+            let (dx, dy) = (axis.0 * i as i64, axis.1 * i as i64);
+            sum += (rgb(source.texel(x + dx, y + dy)) + rgb(source.texel(x - dx, y - dy))) * weight;
+        }
+        from_rgb(sum)
+    });
+}
+
+impl<C: ColorFormat> PostEffect<C> for Bloom {
+    fn apply(&self, input: &PostInput<C>, output: &mut Framebuffer<C>, scratch: &mut Scratch<C>) {
+        let color = input.color;
+        let (width, height) = ((color.width / 2).max(1), (color.height / 2).max(1));
+
+        // Half resolution pixels sit between four full resolution ones, which bilinear
+        // filtering averages
+        let [glow, blurred] = scratch.framebuffers(width, height);
+        draw_fullscreen(glow, |fragment| {
+            let rgb = bilinear(&color, fragment.varyings);
+            let luminance = luminance(rgb);
+            from_rgb(rgb * (luminance - self.threshold).max(0.0) / luminance.max(1e-4))
+        });
+
+        let weights = self.weights();
+        blur(glow.color_texture(), blurred, &weights, (1, 0));
+        blur(blurred.color_texture(), glow, &weights, (0, 1));
+
+        let glow = glow.color_texture();
+        draw_fullscreen(output, |fragment| {
+            let (x, y) = pixel(fragment);
+            from_rgb(rgb(color.texel(x, y)) + bilinear(&glow, fragment.varyings) * self.intensity)
+        });
+    }
+}
+
+/// Darkens the frame towards its corners.
+#[derive(Debug, Copy, Clone)]
+pub struct Vignette {
+    /// Darkness of the corners, from 0 to 1.
+    pub intensity: f32,
+    /// Distance from the center where the darkening starts, 1 being the corners.
+    pub radius: f32,
+    /// Distance over which the darkening fades in.
+    pub softness: f32,
+}
+
+impl Default for Vignette {
+    fn default() -> Self {
+        Self {
+            intensity: 0.4,
+            radius: 0.6,
+            softness: 0.6,
+        }
+    }
+}
+
+impl<C: ColorFormat> PostEffect<C> for Vignette {
+    fn apply(&self, input: &PostInput<C>, output: &mut Framebuffer<C>, _scratch: &mut Scratch<C>) {
+        let color = input.color;
+        let size = Vec2::new(color.width as f32, color.height as f32);
+        draw_fullscreen(output, |fragment| {
+            // Measured in pixels, so the vignette is round whatever the aspect ratio
+            let distance = ((fragment.varyings - 0.5) * size).length() / (size.length() * 0.5);
+            let darkening = smoothstep(self.radius, self.radius + self.softness, distance);
+            let (x, y) = pixel(fragment);
+            from_rgb(rgb(color.texel(x, y)) * (1.0 - self.intensity * darkening))
+        });
+    }
+}
+
+/// Table of `size`³ colors, looked up with trilinear interpolation.
+pub struct Lut {
+    size: usize,
+    /// Colors indexed by `(b * size + g) * size + r`.
+    data: Vec<Vec3>,
+}
+
+impl Lut {
+    /// Samples a grading function, colors are in 0 to 1.
+    pub fn from_fn(size: usize, grade: impl Fn(Vec3) -> Vec3) -> Self {
+        assert!(size >= 2, "A LUT needs at least 2 entries per channel");
+        let scale = 1.0 / (size - 1) as f32;
+        let mut data = Vec::with_capacity(size * size * size);
+        for b in 0..size {
+            for g in 0..size {
+                for r in 0..size {
+                    data.push(grade(Vec3::new(r as f32, g as f32, b as f32) * scale));
+                }
+            }
+        }
+        Self { size, data }
+    }
+
+    /// The LUT that leaves colors as they are.
+    pub fn identity(size: usize) -> Self {
+        Self::from_fn(size, |rgb| rgb)
+    }
+
+    /// Reads a LUT laid out as a horizontal strip of `size` squares of `size` x `size`
+    /// pixels, such as a 256x16 image: blue selects the square, red runs left to right and
+    /// green top to bottom, as `Texture::load` reads images.
+    pub fn from_strip(texture: &Texture) -> Option<Self> {
+        let size = texture.height;
+        if size < 2 || texture.width != size * size {
+            return None;
+        }
+        let view = texture.view();
+        let mut data = Vec::with_capacity(size * size * size);
+        for b in 0..size {
+            for g in 0..size {
+                for r in 0..size {
+                    data.push(rgb(view.texel((b * size + r) as i64, g as i64)));
+                }
+            }
+        }
+        Some(Self { size, data })
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Graded color of a color in 0 to 1, colors outside of it are clamped.
+    pub fn lookup(&self, rgb: Vec3) -> Vec3 {
+        let max = (self.size - 1) as f32;
+        let p = rgb.clamp(Vec3::ZERO, Vec3::ONE) * max;
+        let p0 = p.floor().min(Vec3::splat(max - 1.0));
+        let t = p - p0;
+        let (r, g, b) = (p0.x as usize, p0.y as usize, p0.z as usize);
+
+        let entry = |r: usize, g: usize, b: usize| self.data[(b * self.size + g) * self.size + r];
+        let plane = |b: usize| {
+            let bottom = entry(r, g, b).lerp(entry(r + 1, g, b), t.x);
+            let top = entry(r, g + 1, b).lerp(entry(r + 1, g + 1, b), t.x);
+            bottom.lerp(top, t.y)
+        };
+        plane(b).lerp(plane(b + 1), t.z)
+    }
+}
+
+/// Maps every color through a lookup table. HDR colors are clamped by the lookup, so HDR
+/// frames are better graded once tone mapped.
+pub struct ColorGrading {
+    pub lut: Lut,
+    /// Blend from the original colors, at 0, to the graded ones, at 1.
+    pub strength: f32,
+}
+
+impl<C: ColorFormat> PostEffect<C> for ColorGrading {
+    fn apply(&self, input: &PostInput<C>, output: &mut Framebuffer<C>, _scratch: &mut Scratch<C>) {
+        let color = input.color;
+        draw_fullscreen(output, |fragment| {
+            let (x, y) = pixel(fragment);
+            let rgb = rgb(color.texel(x, y));
+            from_rgb(rgb.lerp(self.lut.lookup(rgb), self.strength))
+        });
+    }
+}
+
+/// Imitates a curved CRT screen, with dark gaps between its scanlines and the stripes of
+/// its aperture grille.
+#[derive(Debug, Copy, Clone)]
+pub struct Crt {
+    /// Darkness of the gaps between the scanlines, from 0 to 1.
+    pub scanline_intensity: f32,
+    /// Distance between scanlines in pixels.
+    pub scanline_period: f32,
+    /// Darkness of the red, green and blue stripes' other channels, from 0 to 1.
+    pub mask_intensity: f32,
+    /// Bulge of the screen, 0 is flat. The corners bend off screen and turn black.
+    pub curvature: f32,
+}
+
+impl Default for Crt {
+    fn default() -> Self {
+        Self {
+            scanline_intensity: 0.25,
+            scanline_period: 3.0,
+            mask_intensity: 0.1,
+            curvature: 0.1,
+        }
+    }
+}
+
+impl<C: ColorFormat> PostEffect<C> for Crt {
+    fn apply(&self, input: &PostInput<C>, output: &mut Framebuffer<C>, _scratch: &mut Scratch<C>) {
+        let color = input.color;
+        let size = Vec2::new(color.width as f32, color.height as f32);
+        draw_fullscreen(output, |fragment| {
+            let offset = fragment.varyings - 0.5;
+            let uv = offset * (1.0 + self.curvature * offset.length_squared()) + 0.5;
+            if uv.cmplt(Vec2::ZERO).any() || uv.cmpgt(Vec2::ONE).any() {
+                return from_rgb(Vec3::ZERO);
+            }
+
+            // The scanlines follow the curved screen
+            let row = uv.y * size.y;
+            let gap = 0.5 + 0.5 * (2.0 * PI * row / self.scanline_period).cos();
+            let scanline = 1.0 - self.scanline_intensity * gap;
+
+            let stripe = fragment.position.x as usize % 3;
+            let mut mask = Vec3::splat(1.0 - self.mask_intensity);
+            mask[stripe] = 1.0;
+
+            from_rgb(bilinear(&color, uv) * scanline * mask)
+        });
+    }
+}
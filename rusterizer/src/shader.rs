@@ -0,0 +1,264 @@
+use std::ops::{Add, Mul, Sub};
+
+use glam::{Mat4, Vec2, Vec3, Vec4};
+use shared::{
+    camera::Camera,
+    mesh::{GltfVertex, Vertex},
+    texture::{Sampler, TextureView},
+    transform::Transform,
+};
+
+use crate::color::{Color, ColorFormat};
+
+/// Values written by a vertex shader and interpolated across the triangle for the fragment
+/// shader. Anything that can be interpolated through `Add`/`Sub`/`Mul<f32>` qualifies,
+/// structs can use `impl_varyings!` to derive those per field.
+pub trait Varyings:
+    Copy + Add<Output = Self> + Sub<Output = Self> + Mul<f32, Output = Self> + Send + Sync
+{
+}
+
+impl<T> Varyings for T where
+    T: Copy + Add<Output = T> + Sub<Output = T> + Mul<f32, Output = T> + Send + Sync
+{
+}
+
+/// Implements the arithmetic needed by `Varyings` for a struct, field by field.
+#[macro_export]
+macro_rules! impl_varyings {
+    ($name:ident { $($field:ident),* $(,)? }) => {
+        impl std::ops::Add for $name {
+            type Output = Self;
+
+            fn add(self, rhs: Self) -> Self {
+                Self {
+                    $($field: self.$field + rhs.$field),*
+                }
+            }
+        }
+
+        impl std::ops::Sub for $name {
+            type Output = Self;
+
+            fn sub(self, rhs: Self) -> Self {
+                Self {
+                    $($field: self.$field - rhs.$field),*
+                }
+            }
+        }
+
+        impl std::ops::Mul<f32> for $name {
+            type Output = Self;
+
+            fn mul(self, rhs: f32) -> Self {
+                Self {
+                    $($field: self.$field * rhs),*
+                }
+            }
+        }
+    };
+}
+
+/// A vertex after the vertex shader: its clip-space position and the values to interpolate.
+#[derive(Debug, Copy, Clone)]
+pub struct VertexOutput<V> {
+    pub position: Vec4,
+    pub varyings: V,
+}
+
+impl<V: Varyings> Add for VertexOutput<V> {
+    type Output = Self;
+
+    fn add(self, rhs: Self) -> Self {
+        Self {
+            position: self.position + rhs.position,
+            varyings: self.varyings + rhs.varyings,
+        }
+    }
+}
+
+impl<V: Varyings> Sub for VertexOutput<V> {
+    type Output = Self;
+
+    fn sub(self, rhs: Self) -> Self {
+        Self {
+            position: self.position - rhs.position,
+            varyings: self.varyings - rhs.varyings,
+        }
+    }
+}
+
+impl<V: Varyings> Mul<f32> for VertexOutput<V> {
+    type Output = Self;
+
+    fn mul(self, rhs: f32) -> Self {
+        Self {
+            position: self.position * rhs,
+            varyings: self.varyings * rhs,
+        }
+    }
+}
+
+/// A covered pixel handed to the fragment shader.
+#[derive(Debug, Copy, Clone)]
+pub struct Fragment<V> {
+    /// Perspective-correct interpolated varyings.
+    pub varyings: V,
+    /// Screen-space derivatives of the varyings, from the pixel's 2x2 quad.
+    pub ddx: V,
+    pub ddy: V,
+    /// Pixel center in screen space.
+    pub position: Vec2,
+    /// Normalized device depth, from 0 at the near plane to 1 at the far plane.
+    pub depth: f32,
+    pub front_facing: bool,
+}
+
+/// Shaders are generic over their uniforms `U`: a user-defined struct passed by reference to
+/// every invocation of both stages of a draw call. Vertex shaders are also generic over the
+/// vertex type `V` of the meshes they read, fragment shaders over the color format `C` of the
+/// targets they write.
+pub trait VertexShader<U, V = Vertex>: Sync {
+    type Varyings: Varyings;
+
+    fn vertex(&self, uniforms: &U, vertex: &V) -> VertexOutput<Self::Varyings>;
+}
+
+pub trait FragmentShader<U, C = u32>: Sync {
+    type Varyings: Varyings;
+
+    /// Returns the color of the fragment, or `None` to discard it.
+    fn fragment(&self, uniforms: &U, fragment: &Fragment<Self::Varyings>) -> Option<C>;
+}
+
+/// Object and camera matrices of a draw call. Uniforms expose them through `AsRef` to be
+/// usable with the built-in vertex shaders.
+#[derive(Debug, Copy, Clone)]
+pub struct Transforms {
+    pub model: Mat4,
+    pub view: Mat4,
+    pub projection: Mat4,
+}
+
+impl Transforms {
+    pub fn new(transform: &Transform, cam: &Camera) -> Self {
+        Self::from_model(transform.local(), cam)
+    }
+
+    /// Transforms for a model matrix, such as a scene node's world matrix.
+    pub fn from_model(model: Mat4, cam: &Camera) -> Self {
+        Self {
+            model,
+            view: cam.view(),
+            projection: cam.projection(),
+        }
+    }
+
+    pub fn model_view_projection(&self) -> Mat4 {
+        self.projection * self.view * self.model
+    }
+}
+
+impl AsRef<Transforms> for Transforms {
+    fn as_ref(&self) -> &Transforms {
+        self
+    }
+}
+
+/// Uniforms of the built-in fragment shaders, with a texture of texels `T`.
+pub struct BasicUniforms<'a, T = u32> {
+    pub transforms: Transforms,
+    /// A loaded texture's `view`, or an attachment of a framebuffer rendered earlier.
+    pub texture: Option<TextureView<'a, T>>,
+    pub sampler: Sampler,
+    /// Background that transparent texels are blended against.
+    pub clear_color: Color,
+}
+
+impl<T> AsRef<Transforms> for BasicUniforms<'_, T> {
+    fn as_ref(&self) -> &Transforms {
+        &self.transforms
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct BasicVaryings {
+    pub color: Vec3,
+    pub uv: Vec2,
+}
+
+impl_varyings!(BasicVaryings { color, uv });
+
+/// Transforms vertices to clip space and passes their color and uv on.
+pub struct TransformShader;
+
+impl<U: AsRef<Transforms> + Sync> VertexShader<U> for TransformShader {
+    type Varyings = BasicVaryings;
+
+    fn vertex(&self, uniforms: &U, vertex: &Vertex) -> VertexOutput<BasicVaryings> {
+        VertexOutput {
+            position: uniforms.as_ref().model_view_projection() * vertex.position,
+            varyings: BasicVaryings {
+                color: vertex.color,
+                uv: vertex.uv,
+            },
+        }
+    }
+}
+
+impl<U: AsRef<Transforms> + Sync> VertexShader<U, GltfVertex> for TransformShader {
+    type Varyings = BasicVaryings;
+
+    fn vertex(&self, uniforms: &U, vertex: &GltfVertex) -> VertexOutput<BasicVaryings> {
+        VertexOutput {
+            position: uniforms.as_ref().model_view_projection() * vertex.position,
+            varyings: BasicVaryings {
+                color: vertex.color.truncate(),
+                uv: vertex.uv,
+            },
+        }
+    }
+}
+
+/// Samples the texture, blended over the clear color by its alpha in linear light.
+/// Falls back to the vertex color without a texture.
+pub struct TextureShader;
+
+impl<C: ColorFormat> FragmentShader<BasicUniforms<'_, C>, C> for TextureShader {
+    type Varyings = BasicVaryings;
+
+    fn fragment(
+        &self,
+        uniforms: &BasicUniforms<C>,
+        fragment: &Fragment<BasicVaryings>,
+    ) -> Option<C> {
+        match uniforms.texture {
+            Some(texture) => {
+                let col = texture
+                    .sample(
+                        &uniforms.sampler,
+                        fragment.varyings.uv,
+                        fragment.ddx.uv,
+                        fragment.ddy.uv,
+                    )
+                    .to_linear();
+                let background = uniforms.clear_color.to_argb8().to_linear().truncate();
+                Some(C::from_linear(
+                    background.lerp(col.truncate(), col.w).extend(col.w),
+                ))
+            }
+            None => VertexColorShader.fragment(uniforms, fragment),
+        }
+    }
+}
+
+/// Writes the vertex color, which is linear light.
+pub struct VertexColorShader;
+
+impl<U: Sync, C: ColorFormat> FragmentShader<U, C> for VertexColorShader {
+    type Varyings = BasicVaryings;
+
+    fn fragment(&self, _uniforms: &U, fragment: &Fragment<BasicVaryings>) -> Option<C> {
+        Some(C::from_linear(fragment.varyings.color.extend(1.0)))
+    }
+}
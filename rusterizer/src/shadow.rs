@@ -0,0 +1,185 @@
+//! Shadow maps: the depth of the scene as seen from a light, rendered in a depth-only pass
+//! and compared against by the shaders lighting the scene.
+
+use std::f32::consts::PI;
+
+use glam::{Mat4, Vec3};
+use shared::{framebuffer::Framebuffer, light::LightKind, scene::Scene};
+
+use crate::{
+    geometry::{CullMode, RenderState},
+    pass::RenderPass,
+    shader::{TransformShader, Transforms},
+    utils::lerp,
+};
+
+/// Depth of the scene as seen from one of its lights, fitted around the scene's bounds.
+pub struct ShadowMap {
+    /// Index into `Scene::lights` of the light casting the shadows.
+    pub light: usize,
+    pub view: Mat4,
+    pub projection: Mat4,
+    /// Depth-only target the shadow casters are drawn into.
+    pub target: Framebuffer,
+    /// Subtracted from the depth of the receiving surface before the comparison, against
+    /// surfaces shadowing themselves.
+    pub bias: f32,
+    /// Distance in world units the receiving surface is moved along its normal before the
+    /// comparison, against shadow acne at grazing angles.
+    pub normal_bias: f32,
+    /// Number of texels on each side of the sample the comparisons are averaged over, 0
+    /// compares a single bilinear footprint.
+    pub pcf_radius: u32,
+}
+
+impl ShadowMap {
+    /// Creates a `size` x `size` shadow map for a light of the scene. Directional lights use an
+    /// orthographic projection and spot lights a perspective one covering their outer cone.
+    /// Returns `None` for point lights, which would need a map for every direction.
+    pub fn new(scene: &Scene, light: usize, size: usize) -> Option<Self> {
+        let (center, radius) = scene_bounds(scene);
+        let up = |direction: Vec3| match direction.cross(Vec3::Y).length_squared() < 1e-6 {
+            true => Vec3::X,
+            false => Vec3::Y,
+        };
+
+        // World space size of a texel at the scene's center, the default normal bias
+        let (view, projection, texel_size) = match scene.lights[light].kind {
+            LightKind::Directional { direction } => {
+                let eye = center - direction * radius;
+                (
+                    Mat4::look_at_rh(eye, center, up(direction)),
+                    Mat4::orthographic_rh(-radius, radius, -radius, radius, 0.0, 2.0 * radius),
+                    2.0 * radius / size as f32,
+                )
+            }
+            LightKind::Spot {
+                position,
+                direction,
+                outer_angle,
+                ..
+            } => {
+                let distance = position.distance(center);
+                let far = distance + radius;
+                let near = (distance - radius).max(far * 1e-3);
+                let fov = (2.0 * outer_angle).min(PI * 0.95);
+                (
+                    Mat4::look_at_rh(position, position + direction, up(direction)),
+                    Mat4::perspective_rh(fov, 1.0, near, far),
+                    2.0 * distance * (fov / 2.0).tan() / size as f32,
+                )
+            }
+            LightKind::Point { .. } => return None,
+        };
+
+        Some(Self {
+            light,
+            view,
+            projection,
+            target: Framebuffer::depth_only(size, size),
+            bias: 0.001,
+            normal_bias: texel_size,
+            pcf_radius: 1,
+        })
+    }
+
+    /// Transforms drawing a model with the given world matrix into the shadow map.
+    pub fn transforms(&self, model: Mat4) -> Transforms {
+        Transforms {
+            model,
+            view: self.view,
+            projection: self.projection,
+        }
+    }
+
+    /// Clears the shadow map and draws the depth of every model instance of the scene.
+    pub fn render(&mut self, scene: &Scene) {
+        let instances: Vec<_> = scene
+            .instances()
+            .into_iter()
+            .map(|(world, model)| (self.transforms(world), model))
+            .collect();
+
+        // Both sides cast shadows, so open meshes such as planes do too
+        let mut render_state = RenderState::new();
+        render_state.cull_mode = CullMode::None;
+
+        let mut pass = RenderPass::clear(&mut self.target, 0);
+        for (transforms, model) in &instances {
+            for primitive in &model.primitives {
+                pass.draw_depth(&primitive.mesh, &render_state, transforms, &TransformShader);
+            }
+        }
+        pass.finish();
+    }
+
+    /// How much of the light reaches a world space position with the given surface normal,
+    /// from 0 in full shadow to 1. Positions outside of the shadow map are lit.
+    ///
+    /// The comparisons of the texels around the position are bilinearly filtered and
+    /// averaged over the PCF kernel, giving the shadow a soft edge.
+    pub fn visibility(&self, position: Vec3, normal: Vec3) -> f32 {
+        let position = position + normal.normalize_or_zero() * self.normal_bias;
+        let clip = self.projection * self.view * position.extend(1.0);
+        if clip.w <= 0.0 {
+            return 1.0;
+        }
+        let ndc = clip.truncate() / clip.w;
+        if ndc.z > 1.0 {
+            return 1.0;
+        }
+        let depth = ndc.z - self.bias;
+
+        // Same mapping to the target as the rasterizer, relative to the texel centers
+        let (width, height) = (self.target.width, self.target.height);
+        let x = (ndc.x + 1.0) * 0.5 * width as f32 - 0.5;
+        let y = (1.0 - ndc.y) * 0.5 * height as f32 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as i64, y0 as i64);
+
+        let lit = |x: i64, y: i64| {
+            if x < 0 || y < 0 || x >= width as i64 || y >= height as i64 {
+                return 1.0;
+            }
+            let occluder = self.target.depth[self.target.index(x as usize, y as usize)];
+            if depth <= occluder {
+                1.0
+            } else {
+                0.0
+            }
+        };
+
+        let radius = self.pcf_radius as i64;
+        let mut sum = 0.0;
+        for dy in -radius..=radius {
+            for dx in -radius..=radius {
+                let (x, y) = (x0 + dx, y0 + dy);
+                let top = lerp(lit(x, y), lit(x + 1, y), fx);
+                let bottom = lerp(lit(x, y + 1), lit(x + 1, y + 1), fx);
+                sum += lerp(top, bottom, fy);
+            }
+        }
+        let taps = (2 * radius + 1) * (2 * radius + 1);
+        sum / taps as f32
+    }
+}
+
+/// Center and radius of a sphere around every model instance of the scene.
+fn scene_bounds(scene: &Scene) -> (Vec3, f32) {
+    let mut min = Vec3::splat(f32::INFINITY);
+    let mut max = Vec3::splat(f32::NEG_INFINITY);
+    for (world, model) in scene.instances() {
+        for primitive in &model.primitives {
+            for vertex in &primitive.mesh.vertices {
+                let position = world.transform_point3(vertex.position.truncate());
+                min = min.min(position);
+                max = max.max(position);
+            }
+        }
+    }
+    if min.x > max.x {
+        return (Vec3::ZERO, 1.0);
+    }
+    ((min + max) / 2.0, (max - min).length().max(1e-3) / 2.0)
+}
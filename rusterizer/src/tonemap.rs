@@ -0,0 +1,55 @@
+//! Tone mapping: the unbounded linear light of an HDR frame compressed into the displayable
+//! range, then sRGB encoded into the 8-bit framebuffer that is presented.
+
+use glam::{Vec3, Vec4};
+use shared::framebuffer::Framebuffer;
+
+use crate::color::ColorFormat;
+
+/// Operators mapping linear light to 0 to 1.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ToneMapping {
+    /// `c / (1 + c)`: dark colors stay about the same, highlights are compressed towards white
+    /// but never reach it.
+    Reinhard,
+    /// Krzysztof Narkowicz's fit of the ACES filmic curve, which adds contrast with a toe in
+    /// the shadows and a shoulder rolling highlights off to white.
+    AcesFilmic,
+    /// `1 - exp(-exposure * c)`, like film exposed for the given time. Higher exposures
+    /// brighten the frame.
+    Exposure(f32),
+}
+
+impl ToneMapping {
+    /// Maps a linear color to 0 to 1, still in linear light.
+    pub fn map(&self, color: Vec3) -> Vec3 {
+        let color = color.max(Vec3::ZERO);
+        match *self {
+            ToneMapping::Reinhard => color / (Vec3::ONE + color),
+            ToneMapping::AcesFilmic => {
+                let (a, b, c, d, e) = (2.51, 0.03, 2.43, 0.59, 0.14);
+                (color * (a * color + b) / (color * (c * color + d) + e))
+                    .clamp(Vec3::ZERO, Vec3::ONE)
+            }
+            ToneMapping::Exposure(exposure) => Vec3::ONE - (-exposure * color).exp(),
+        }
+    }
+}
+
+/// Tone maps an HDR frame and writes it sRGB encoded to a target of the same size, ready to
+/// be displayed. Multisampled frames are resolved first, in linear light, and every sample
+/// of a multisampled target receives the result.
+pub fn present(frame: &Framebuffer<Vec4>, tone_mapping: ToneMapping, target: &mut Framebuffer) {
+    assert_eq!(
+        (frame.width, frame.height),
+        (target.width, target.height),
+        "The frame and the target have to be the same size"
+    );
+
+    let samples = target.samples;
+    for (pixel, color) in frame.resolve().iter().enumerate() {
+        let mapped = tone_mapping.map(color.truncate());
+        let first = pixel * samples;
+        target.color[first..first + samples].fill(u32::from_linear(mapped.extend(1.0)));
+    }
+}
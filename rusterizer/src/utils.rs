@@ -1,9 +1,9 @@
-use glam::{Vec2, Vec3};
-use shared::State;
+use glam::{I64Vec2, Vec2, Vec3, Vec4};
+use shared::framebuffer::{Framebuffer, MAX_SAMPLES};
 
 use crate::color::Color;
 
-fn plotline_low(v0: Vec2, v1: Vec2, color: Color, state: &State) {
+fn plotline_low(v0: Vec2, v1: Vec2, color: Color, target: &mut Framebuffer) {
     let dx = v1.x - v0.x;
     let mut dy = v1.y - v0.y;
     let mut yi = 1.0;
@@ -15,7 +15,7 @@ fn plotline_low(v0: Vec2, v1: Vec2, color: Color, state: &State) {
     let mut y = v0.y;
 
     for x in v0.x as usize..v1.x as usize {
-        state.draw(x as u16, y as u16, color.to_argb8());
+        target.set_pixel(x, y as usize, color.to_argb8());
         if d >= 0.0 {
             y += yi;
             d += 2.0 * (dy - dx);
@@ -25,7 +25,7 @@ fn plotline_low(v0: Vec2, v1: Vec2, color: Color, state: &State) {
     }
 }
 
-fn plotline_high(v0: Vec2, v1: Vec2, color: Color, state: &State) {
+fn plotline_high(v0: Vec2, v1: Vec2, color: Color, target: &mut Framebuffer) {
     let mut dx = v1.x - v0.x;
     let dy = v1.y - v0.y;
     let mut xi = 1.0;
@@ -37,7 +37,7 @@ fn plotline_high(v0: Vec2, v1: Vec2, color: Color, state: &State) {
     let mut x = v0.x;
 
     for y in v0.y as usize..v1.y as usize {
-        state.draw(x as u16, y as u16, color.to_argb8());
+        target.set_pixel(x as usize, y, color.to_argb8());
         if d >= 0.0 {
             x += xi;
             d += 2.0 * (dx - dy);
@@ -48,24 +48,104 @@ fn plotline_high(v0: Vec2, v1: Vec2, color: Color, state: &State) {
 }
 
 // Bresenham's line algorithm
-pub fn plotline(v0: Vec2, v1: Vec2, color: Color, state: &State) {
+pub fn plotline(v0: Vec2, v1: Vec2, color: Color, target: &mut Framebuffer) {
     if (v1.y - v0.y).abs() < (v1.x - v0.x).abs() {
         if v0.x > v1.x {
-            plotline_low(v1, v0, color, state);
+            plotline_low(v1, v0, color, target);
         } else {
-            plotline_low(v0, v1, color, state);
+            plotline_low(v0, v1, color, target);
         }
     } else {
         if v0.y > v1.y {
-            plotline_high(v1, v0, color, state);
+            plotline_high(v1, v0, color, target);
         } else {
-            plotline_high(v0, v1, color, state);
+            plotline_high(v0, v1, color, target);
         }
     }
 }
 
-pub fn edge_function_cw(v0: Vec2, v1: Vec2, p: Vec2) -> f32 {
-    (p.x - v0.x) * (v1.y - v0.y) - (p.y - v0.y) * (v1.x - v0.x)
+/// Number of fractional bits of the fixed-point screen coordinates used by the rasterizer.
+pub const SUBPIXEL_BITS: u32 = 8;
+pub const SUBPIXEL_ONE: i64 = 1 << SUBPIXEL_BITS;
+
+/// Snapped coordinates are limited to this many pixels away from the origin, which keeps
+/// the edge function products within `i64`.
+const MAX_SCREEN_COORD: f32 = (1 << 21) as f32;
+
+/// Snaps a screen-space position to the subpixel grid.
+pub fn snap_to_subpixel(p: Vec2) -> I64Vec2 {
+    let p = p.clamp(
+        Vec2::splat(-MAX_SCREEN_COORD),
+        Vec2::splat(MAX_SCREEN_COORD),
+    );
+    (p * SUBPIXEL_ONE as f32).round().as_i64vec2()
+}
+
+/// Integer edge function `a * x + b * y + c` of the edge `v0 -> v1` in subpixel units,
+/// zero on the edge. Stepping one pixel adds `a` or `b` times `SUBPIXEL_ONE`.
+#[derive(Debug, Copy, Clone)]
+pub struct EdgeFunction {
+    pub a: i64,
+    pub b: i64,
+    pub c: i64,
+    top_left: bool,
+}
+
+impl EdgeFunction {
+    pub fn new(v0: I64Vec2, v1: I64Vec2) -> Self {
+        let a = v1.y - v0.y;
+        let b = v0.x - v1.x;
+        let c = -v0.x * a - v0.y * b;
+
+        // With y pointing down and the inside being positive, a left edge rises along x
+        // and a top edge is horizontal with the inside below it.
+        let top_left = a > 0 || (a == 0 && b > 0);
+
+        Self { a, b, c, top_left }
+    }
+
+    pub fn evaluate(&self, p: I64Vec2) -> i64 {
+        self.a * p.x + self.b * p.y + self.c
+    }
+
+    /// Top-left fill rule: samples exactly on an edge only belong to the triangle if the
+    /// edge is a top or left edge, so shared edges are drawn exactly once.
+    pub fn covers(&self, value: i64) -> bool {
+        value > 0 || (value == 0 && self.top_left)
+    }
+}
+
+/// Standard multisample positions, as offsets from the pixel center in pixels. All of them
+/// lie on the subpixel grid.
+const SAMPLES_1: [Vec2; 1] = [Vec2::ZERO];
+const SAMPLES_2: [Vec2; 2] = [Vec2::new(0.25, 0.25), Vec2::new(-0.25, -0.25)];
+const SAMPLES_4: [Vec2; 4] = [
+    Vec2::new(-0.125, -0.375),
+    Vec2::new(0.375, -0.125),
+    Vec2::new(-0.375, 0.125),
+    Vec2::new(0.125, 0.375),
+];
+const SAMPLES_8: [Vec2; MAX_SAMPLES] = [
+    Vec2::new(0.0625, -0.1875),
+    Vec2::new(-0.0625, 0.1875),
+    Vec2::new(0.3125, 0.0625),
+    Vec2::new(-0.1875, -0.3125),
+    Vec2::new(-0.3125, 0.3125),
+    Vec2::new(-0.4375, -0.0625),
+    Vec2::new(0.1875, 0.4375),
+    Vec2::new(0.4375, -0.4375),
+];
+
+/// Sample positions for a framebuffer with 1, 2, 4 or 8 samples per pixel, the last being
+/// `MAX_SAMPLES`.
+pub fn sample_positions(samples: usize) -> &'static [Vec2] {
+    match samples {
+        1 => &SAMPLES_1,
+        2 => &SAMPLES_2,
+        4 => &SAMPLES_4,
+        8 => &SAMPLES_8,
+        _ => panic!("Unsupported sample count: {}", samples),
+    }
 }
 
 pub fn to_argb8(a: u8, r: u8, g: u8, b: u8) -> u32 {
@@ -76,25 +156,6 @@ pub fn to_argb8(a: u8, r: u8, g: u8, b: u8) -> u32 {
     argb
 }
 
-pub fn barycentric_coordinates(
-    point: Vec2,
-    v0: Vec2,
-    v1: Vec2,
-    v2: Vec2,
-    area: f32,
-) -> Option<Vec3> {
-    let m0 = edge_function_cw(point, v1, v2);
-    let m1 = edge_function_cw(point, v2, v0);
-    let m2 = edge_function_cw(point, v0, v1);
-
-    let a = 1.0 / area;
-    if m0 > 0.0 && m1 > 0.0 && m2 > 0.0 {
-        Some(Vec3::new(m0 * a, m1 * a, m2 * a))
-    } else {
-        None
-    }
-}
-
 pub fn lerp<T>(min: T, max: T, t: f32) -> T
 where
     T: std::ops::Sub<Output = T>
@@ -115,3 +176,17 @@ where
 {
     b1 + (v - a1) * (b2 - b1) / (a2 - a1)
 }
+
+/// Transforms a normal read from a tangent space normal map to the space of `normal` and
+/// `tangent`, the tangent carrying the bitangent's sign in w. `scale` scales the normal's x
+/// and y, as glTF's normal texture scale does.
+pub fn perturb_normal(normal: Vec3, tangent: Vec4, texel: Vec3, scale: f32) -> Vec3 {
+    let n = normal.normalize_or_zero();
+    // Interpolation leaves the tangent slightly off perpendicular
+    let t = (tangent.truncate() - n * n.dot(tangent.truncate())).normalize_or_zero();
+    let b = n.cross(t) * tangent.w.signum();
+
+    let mapped = texel * 2.0 - Vec3::ONE;
+    let mapped = Vec3::new(mapped.x * scale, mapped.y * scale, mapped.z);
+    (t * mapped.x + b * mapped.y + n * mapped.z).normalize_or_zero()
+}
@@ -0,0 +1,69 @@
+//! Loads malformed glTF files, which have to fail with an error instead of panicking or hanging.
+
+use std::fs;
+
+use rusterizer::gltf_import::{load_gltf_scene, GltfError};
+use shared::scene::Scene;
+
+/// Three positions and the indices 0, 1 and 5, embedded as a data URI.
+const BUFFER: &str = "data:application/octet-stream;base64,\
+    AAAAAAAAAAAAAAAAAACAPwAAAAAAAAAAAAAAAAAAgD8AAAAAAAABAAUAAAA=";
+
+/// Writes the document to a directory of this test and process and loads it from there.
+fn load_document(name: &str, document: &str) -> Result<Scene, GltfError> {
+    let dir = std::env::temp_dir().join(format!("rusterizer_gltf_{}_{}", name, std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("scene.gltf"), document).unwrap();
+    let result = load_gltf_scene(&dir.join("scene.gltf"));
+    fs::remove_dir_all(&dir).unwrap();
+    result
+}
+
+#[test]
+fn index_out_of_range() {
+    let document = format!(
+        r#"{{
+            "asset": {{ "version": "2.0" }},
+            "buffers": [{{ "byteLength": 44, "uri": "{}" }}],
+            "bufferViews": [
+                {{ "buffer": 0, "byteOffset": 0, "byteLength": 36 }},
+                {{ "buffer": 0, "byteOffset": 36, "byteLength": 6 }}
+            ],
+            "accessors": [
+                {{
+                    "bufferView": 0, "componentType": 5126, "count": 3, "type": "VEC3",
+                    "min": [0, 0, 0], "max": [1, 1, 0]
+                }},
+                {{ "bufferView": 1, "componentType": 5123, "count": 3, "type": "SCALAR" }}
+            ],
+            "meshes": [{{ "primitives": [{{ "attributes": {{ "POSITION": 0 }}, "indices": 1 }}] }}],
+            "nodes": [{{ "mesh": 0 }}],
+            "scenes": [{{ "nodes": [0] }}]
+        }}"#,
+        BUFFER
+    );
+    match load_document("index", &document) {
+        Err(GltfError::IndexOutOfRange {
+            mesh: 0,
+            primitive: 0,
+            index: 5,
+            vertex_count: 3,
+        }) => (),
+        Err(error) => panic!("Expected an index error, got {}", error),
+        Ok(_) => panic!("Expected an index error"),
+    }
+}
+
+#[test]
+fn node_cycle() {
+    let document = r#"{
+        "asset": { "version": "2.0" },
+        "nodes": [{ "children": [1] }, { "children": [2] }, { "children": [1] }],
+        "scenes": [{ "nodes": [0] }]
+    }"#;
+    match load_document("cycle", document) {
+        Err(GltfError::NodeCycle(1)) => (),
+        Err(error) => panic!("Expected a node cycle error, got {}", error),
+        Ok(_) => panic!("Expected a node cycle error"),
+    }
+}
@@ -0,0 +1,1002 @@
+//! Golden-image tests: canned scenes are rendered headlessly and compared against the
+//! reference images in `tests/golden/`.
+//!
+//! Run `BLESS=1 cargo test --test golden` to write new references. A failing test writes the
+//! rendered image and a diff image to `target/golden/`.
+
+use std::{
+    f32::consts::PI,
+    fs::{self, File},
+    io::BufWriter,
+    path::{Path, PathBuf},
+};
+
+use glam::{Mat3, Mat4, Quat, Vec2, Vec3, Vec4};
+use rusterizer::{
+    binner::Binner,
+    blinn_phong::{BlinnPhongShader, BlinnPhongUniforms},
+    color::Color,
+    geometry::{CullMode, RenderMesh, RenderState},
+    gltf_import::load_gltf_scene,
+    impl_varyings,
+    obj_import::load_obj_scene,
+    pass::RenderPass,
+    pbr::draw_scene,
+    post::{Bloom, ColorGrading, Crt, Lut, PostChain, Vignette},
+    shader::{
+        BasicUniforms, BasicVaryings, Fragment, FragmentShader, TextureShader, TransformShader,
+        Transforms, VertexColorShader, VertexOutput, VertexShader,
+    },
+    shadow::ShadowMap,
+    tonemap::{present, ToneMapping},
+};
+use shared::{
+    camera::Camera,
+    framebuffer::Framebuffer,
+    light::Light,
+    material::{AlphaMode, Material},
+    mesh::{GltfVertex, Mesh, Vertex},
+    scene::{Model, Node, Primitive, Scene},
+    texture::{ColorSpace, Filter, Sampler, Texture, TextureView, WrapMode},
+    to_argb8,
+    transform::Transform,
+    State,
+};
+
+/// Largest per-channel difference for two pixels to be considered equal.
+const TOLERANCE: u8 = 2;
+/// Number of differing pixels allowed before a comparison fails.
+const MAX_DIFF_PIXELS: usize = 8;
+
+const WIDTH: usize = 320;
+const HEIGHT: usize = 240;
+
+struct Image {
+    width: usize,
+    height: usize,
+    rgb: Vec<u8>,
+}
+
+impl Image {
+    fn from_framebuffer(framebuffer: &Framebuffer) -> Self {
+        Self {
+            width: framebuffer.width,
+            height: framebuffer.height,
+            rgb: framebuffer
+                .resolve()
+                .iter()
+                .flat_map(|&argb| [(argb >> 16) as u8, (argb >> 8) as u8, argb as u8])
+                .collect(),
+        }
+    }
+
+    fn load(path: &Path) -> Option<Self> {
+        let decoder = png::Decoder::new(File::open(path).ok()?);
+        let mut reader = decoder.read_info().ok()?;
+        let mut rgb = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut rgb).ok()?;
+        if info.color_type != png::ColorType::Rgb || info.bit_depth != png::BitDepth::Eight {
+            return None;
+        }
+        rgb.truncate(info.buffer_size());
+        Some(Self {
+            width: info.width as usize,
+            height: info.height as usize,
+            rgb,
+        })
+    }
+
+    fn save(&self, path: &Path) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        let writer = BufWriter::new(File::create(path).unwrap());
+        let mut encoder = png::Encoder::new(writer, self.width as u32, self.height as u32);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder
+            .write_header()
+            .unwrap()
+            .write_image_data(&self.rgb)
+            .unwrap();
+    }
+}
+
+fn manifest_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+}
+
+/// Compares the image against its reference, or replaces the reference when blessing.
+fn check(name: &str, actual: Image) {
+    let reference_path = manifest_dir().join(format!("tests/golden/{}.png", name));
+    if std::env::var_os("BLESS").is_some() {
+        actual.save(&reference_path);
+        return;
+    }
+
+    let output_dir = manifest_dir().join("target/golden");
+    let Some(reference) = Image::load(&reference_path) else {
+        actual.save(&output_dir.join(format!("{}.png", name)));
+        panic!(
+            "Missing reference {:?}, run with BLESS=1 to create it",
+            reference_path
+        );
+    };
+    if (reference.width, reference.height) != (actual.width, actual.height) {
+        actual.save(&output_dir.join(format!("{}.png", name)));
+        panic!(
+            "{}: rendered {}x{}, reference is {}x{}",
+            name, actual.width, actual.height, reference.width, reference.height
+        );
+    }
+
+    // Differing pixels are red in the diff image, matching ones a dimmed copy of the reference
+    let mut diff = Vec::with_capacity(reference.rgb.len());
+    let mut diff_pixels = 0;
+    for (expected, got) in reference.rgb.chunks(3).zip(actual.rgb.chunks(3)) {
+        let differs = expected
+            .iter()
+            .zip(got)
+            .any(|(a, b)| a.abs_diff(*b) > TOLERANCE);
+        if differs {
+            diff_pixels += 1;
+            diff.extend([255, 0, 0]);
+        } else {
+            diff.extend(expected.iter().map(|c| c / 4));
+        }
+    }
+
+    if diff_pixels > MAX_DIFF_PIXELS {
+        actual.save(&output_dir.join(format!("{}.png", name)));
+        let diff = Image {
+            rgb: diff,
+            ..reference
+        };
+        diff.save(&output_dir.join(format!("{}.diff.png", name)));
+        panic!(
+            "{}: {} pixels differ from the reference (at most {} allowed), see {:?}",
+            name, diff_pixels, MAX_DIFF_PIXELS, output_dir
+        );
+    }
+}
+
+fn new_state(camera: Camera) -> State {
+    let mut state = State::new(WIDTH, HEIGHT);
+    state.camera = Camera {
+        aspect_ratio: state.camera.aspect_ratio,
+        ..camera
+    };
+    state.framebuffer.clear_color(to_argb8(255, 0, 0, 0));
+    state
+}
+
+fn viewport(framebuffer: &Framebuffer) -> Vec2 {
+    Vec2::new(framebuffer.width as f32, framebuffer.height as f32)
+}
+
+#[test]
+fn synthwave() {
+    let mut state = new_state(Camera {
+        fov: 1.0,
+        transform: Transform::from_translation(Vec3::new(0.0, 1.5, 6.0)),
+        ..Default::default()
+    });
+    // The scene's assets are in the window crate
+    state.asset_root = manifest_dir().join("../window");
+    rusterizer::setup(&mut state);
+
+    // The first frame sets the clear color, render the second one like the host would
+    for _ in 0..2 {
+        state.framebuffer.clear_color(state.clear_color);
+        state.framebuffer.clear_depth();
+        rusterizer::update(&mut state);
+        state.time_passed += 0.5;
+    }
+
+    check("synthwave", Image::from_framebuffer(&state.framebuffer));
+}
+
+#[derive(Debug, Copy, Clone)]
+struct NormalVaryings {
+    normal: Vec3,
+}
+
+impl_varyings!(NormalVaryings { normal });
+
+/// Colors surfaces by their world space normal.
+struct NormalShader;
+
+impl VertexShader<Transforms, GltfVertex> for NormalShader {
+    type Varyings = NormalVaryings;
+
+    fn vertex(&self, uniforms: &Transforms, vertex: &GltfVertex) -> VertexOutput<NormalVaryings> {
+        VertexOutput {
+            position: uniforms.model_view_projection() * vertex.position,
+            varyings: NormalVaryings {
+                normal: Mat3::from_mat4(uniforms.model) * vertex.normal,
+            },
+        }
+    }
+}
+
+impl FragmentShader<Transforms> for NormalShader {
+    type Varyings = NormalVaryings;
+
+    fn fragment(&self, _uniforms: &Transforms, fragment: &Fragment<NormalVaryings>) -> Option<u32> {
+        let color = fragment.varyings.normal.normalize() * 0.5 + 0.5;
+        Some(to_argb8(
+            255,
+            (color.x * 255.0) as u8,
+            (color.y * 255.0) as u8,
+            (color.z * 255.0) as u8,
+        ))
+    }
+}
+
+/// Draws every model instance of the scene, rotated as a whole, with the normal shader.
+fn render_scene(scene: &Scene, rotation: Quat, camera: Camera, samples: usize) -> Framebuffer {
+    let mut state = new_state(camera);
+    state.set_samples(samples);
+    state.framebuffer.clear_color(to_argb8(255, 0, 0, 0));
+
+    let instances = scene.instances();
+    let uniforms: Vec<_> = instances
+        .iter()
+        .map(|(world, _)| Transforms::from_model(Mat4::from_quat(rotation) * *world, &state.camera))
+        .collect();
+
+    let render_state = RenderState::new();
+    let mut binner = Binner::new(viewport(&state.framebuffer));
+    for ((_, model), uniforms) in instances.iter().zip(&uniforms) {
+        for primitive in &model.primitives {
+            RenderMesh::from_mesh(&primitive.mesh).draw_mesh(
+                &render_state,
+                uniforms,
+                &NormalShader,
+                &NormalShader,
+                &mut binner,
+            );
+        }
+    }
+    binner.flush(&mut state.framebuffer);
+
+    state.framebuffer
+}
+
+fn render_cube(samples: usize) -> Framebuffer {
+    let path = manifest_dir().join("../window/assets/models/cube/cube.gltf");
+    let scene = load_gltf_scene(&path).expect("Failed to load cube.gltf");
+    let rotation = Quat::from_euler(glam::EulerRot::YXZ, PI / 5.0, PI / 7.0, 0.0);
+    let camera = Camera {
+        transform: Transform::from_translation(Vec3::new(0.0, 0.0, 6.0)),
+        ..Default::default()
+    };
+    render_scene(&scene, rotation, camera, samples)
+}
+
+#[test]
+fn cube() {
+    check("cube", Image::from_framebuffer(&render_cube(1)));
+}
+
+#[test]
+fn cube_msaa() {
+    check("cube_msaa", Image::from_framebuffer(&render_cube(4)));
+}
+
+/// A white directional light and some ambient light.
+fn add_sun(scene: &mut Scene) {
+    scene.lights.push(Light::directional(
+        Vec3::new(-0.5, -1.0, -0.8),
+        Vec3::ONE,
+        3.0,
+    ));
+    scene.ambient_light = Vec3::splat(0.3);
+}
+
+fn load_helmet() -> Scene {
+    let path = manifest_dir().join("../window/assets/models/damaged_helmet/DamagedHelmet.gltf");
+    load_gltf_scene(&path).expect("Failed to load DamagedHelmet.gltf")
+}
+
+#[test]
+fn helmet() {
+    let scene = load_helmet();
+
+    assert_eq!(scene.roots.len(), 1);
+    assert_eq!(scene.models[0].primitives.len(), 1);
+    assert_eq!(scene.textures.len(), 5);
+    let material = scene.material(&scene.models[0].primitives[0]);
+    assert_eq!(material.name.as_deref(), Some("Material_MR"));
+    assert!(material.base_color_texture.is_some());
+
+    // The file has no tangents, generated ones are perpendicular to the normals
+    for vertex in &scene.models[0].primitives[0].mesh.vertices {
+        let tangent = vertex.tangent.truncate();
+        assert!((tangent.length() - 1.0).abs() < 1e-3);
+        assert!(tangent.dot(vertex.normal.normalize()).abs() < 1e-3);
+        assert_eq!(vertex.tangent.w.abs(), 1.0);
+    }
+
+    // The node's rotation turns the helmet's front towards the camera
+    let camera = Camera {
+        transform: Transform::from_translation(Vec3::new(0.0, 0.0, 3.5)),
+        ..Default::default()
+    };
+    let framebuffer = render_scene(&scene, Quat::IDENTITY, camera, 1);
+    check("helmet", Image::from_framebuffer(&framebuffer));
+}
+
+#[test]
+fn helmet_pbr() {
+    let mut scene = load_helmet();
+    add_sun(&mut scene);
+    let material = &scene.materials[0];
+    assert!(material.metallic_roughness_texture.is_some());
+    assert!(material.normal_texture.is_some());
+    assert!(material.occlusion_texture.is_some());
+    assert!(material.emissive_texture.is_some());
+    assert_eq!(material.emissive_factor, Vec3::ONE);
+
+    let mut state = new_state(Camera {
+        transform: Transform::from_translation(Vec3::new(0.0, 0.0, 3.5)),
+        ..Default::default()
+    });
+    draw_scene(&scene, &state.camera, None, &mut state.framebuffer);
+
+    check("helmet_pbr", Image::from_framebuffer(&state.framebuffer));
+}
+
+#[test]
+fn obj() {
+    let mut scene = load_obj_scene(&manifest_dir().join("tests/obj/scene.obj")).unwrap();
+    add_sun(&mut scene);
+
+    let mut state = new_state(Camera {
+        transform: Transform::from_translation_rotation(
+            Vec3::new(5.0, 3.5, 10.0),
+            Quat::from_rotation_y(0.35) * Quat::from_rotation_x(-0.3),
+        ),
+        ..Default::default()
+    });
+    draw_scene(&scene, &state.camera, None, &mut state.framebuffer);
+
+    check("obj", Image::from_framebuffer(&state.framebuffer));
+}
+
+/// The OBJ scene on a floor, with every material receiving shadows.
+fn shadow_scene() -> Scene {
+    let mut scene = load_obj_scene(&manifest_dir().join("tests/obj/scene.obj")).unwrap();
+
+    let mut floor = Mesh::new();
+    let mut triangles = vec![glam::UVec3::new(0, 1, 2), glam::UVec3::new(0, 2, 3)];
+    let mut vertices = [(-8.0, -8.0), (-8.0, 8.0), (8.0, 8.0), (8.0, -8.0)]
+        .iter()
+        .map(|&(x, z)| GltfVertex {
+            position: Vec4::new(x, -1.0, z, 1.0),
+            normal: Vec3::Y,
+            ..Default::default()
+        })
+        .collect();
+    floor.add_vertices(&mut triangles, &mut vertices);
+
+    scene.materials.push(Material {
+        base_color_factor: Vec4::new(0.6, 0.6, 0.7, 1.0),
+        metallic_factor: 0.0,
+        ..Default::default()
+    });
+    scene.models.push(Model {
+        name: Some("floor".to_string()),
+        primitives: vec![Primitive {
+            mesh: floor,
+            material: Some(scene.materials.len() - 1),
+        }],
+    });
+    scene.nodes.push(Node {
+        name: None,
+        transform: Transform::IDENTITY,
+        model: Some(scene.models.len() - 1),
+        children: Vec::new(),
+    });
+    scene.roots.push(scene.nodes.len() - 1);
+
+    for material in &mut scene.materials {
+        material.receive_shadows = true;
+    }
+    scene.ambient_light = Vec3::splat(0.2);
+    scene
+}
+
+fn shadow_camera() -> Camera {
+    Camera {
+        transform: Transform::from_translation_rotation(
+            Vec3::new(5.0, 3.5, 10.0),
+            Quat::from_rotation_y(0.35) * Quat::from_rotation_x(-0.3),
+        ),
+        ..Default::default()
+    }
+}
+
+fn render_shadows(scene: &Scene) -> Image {
+    let mut shadow = ShadowMap::new(scene, 0, 512).unwrap();
+    shadow.render(scene);
+    assert!(shadow.target.color.is_empty());
+
+    let mut state = new_state(shadow_camera());
+    draw_scene(scene, &state.camera, Some(&shadow), &mut state.framebuffer);
+    Image::from_framebuffer(&state.framebuffer)
+}
+
+#[test]
+fn shadows() {
+    let mut scene = shadow_scene();
+    scene.lights.push(Light::directional(
+        Vec3::new(0.6, -1.0, 0.5),
+        Vec3::ONE,
+        3.0,
+    ));
+    check("shadows", render_shadows(&scene));
+
+    scene.lights[0] = Light::spot(
+        Vec3::new(-3.0, 5.0, -2.5),
+        Vec3::new(0.6, -1.0, 0.5),
+        0.5,
+        0.7,
+        Vec3::ONE,
+        60.0,
+    );
+    check("shadows_spot", render_shadows(&scene));
+
+    // Point lights would need a cube map
+    scene.lights[0] = Light::point(Vec3::Y, Vec3::ONE, 1.0);
+    assert!(ShadowMap::new(&scene, 0, 512).is_none());
+}
+
+/// Adds a double-sided square facing +z with a blended material of the given color.
+fn add_translucent_quad(scene: &mut Scene, center: Vec3, color: Vec4) {
+    let mut quad = Mesh::new();
+    let mut triangles = vec![glam::UVec3::new(0, 1, 2), glam::UVec3::new(0, 2, 3)];
+    let mut vertices = [(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)]
+        .iter()
+        .map(|&(x, y)| GltfVertex {
+            position: (center + Vec3::new(x, y, 0.0)).extend(1.0),
+            normal: Vec3::Z,
+            ..Default::default()
+        })
+        .collect();
+    quad.add_vertices(&mut triangles, &mut vertices);
+
+    scene.materials.push(Material {
+        base_color_factor: color,
+        metallic_factor: 0.0,
+        alpha_mode: AlphaMode::Blend,
+        double_sided: true,
+        ..Default::default()
+    });
+    scene.models.push(Model {
+        name: None,
+        primitives: vec![Primitive {
+            mesh: quad,
+            material: Some(scene.materials.len() - 1),
+        }],
+    });
+    scene.nodes.push(Node {
+        name: None,
+        transform: Transform::IDENTITY,
+        model: Some(scene.models.len() - 1),
+        children: Vec::new(),
+    });
+    scene.roots.push(scene.nodes.len() - 1);
+}
+
+#[test]
+fn blending() {
+    let mut scene = shadow_scene();
+    scene.lights.push(Light::directional(
+        Vec3::new(-0.3, -1.0, -0.6),
+        Vec3::ONE,
+        3.0,
+    ));
+    // Added front to back, so they only blend correctly once sorted. The farthest one is
+    // partly hidden by the opaque objects, which it must not hide in turn.
+    add_translucent_quad(
+        &mut scene,
+        Vec3::new(0.5, 0.3, -1.5),
+        Vec4::new(0.1, 0.3, 1.0, 0.4),
+    );
+    add_translucent_quad(
+        &mut scene,
+        Vec3::new(0.8, -0.2, 2.5),
+        Vec4::new(0.1, 1.0, 0.2, 0.5),
+    );
+    add_translucent_quad(
+        &mut scene,
+        Vec3::new(2.8, 1.0, 4.5),
+        Vec4::new(1.0, 0.1, 0.1, 0.6),
+    );
+
+    let mut state = new_state(shadow_camera());
+    draw_scene(&scene, &state.camera, None, &mut state.framebuffer);
+
+    check("blending", Image::from_framebuffer(&state.framebuffer));
+}
+
+#[test]
+fn lights() {
+    let path = manifest_dir().join("../window/assets/models/cube/cube.gltf");
+    let cube = load_gltf_scene(&path).expect("Failed to load cube.gltf");
+    let cube = &cube.models[0].primitives[0].mesh;
+
+    // Floor below the cube, with the normals given by its vertices
+    let mut floor = Mesh::new();
+    let mut triangles = vec![glam::UVec3::new(0, 1, 2), glam::UVec3::new(0, 2, 3)];
+    let mut vertices = [(-6.0, 6.0), (6.0, 6.0), (6.0, -6.0), (-6.0, -6.0)]
+        .iter()
+        .map(|&(x, z)| Vertex {
+            position: Vec4::new(x, -1.0, z, 1.0),
+            normal: Vec3::Y,
+            color: Vec3::splat(0.8),
+            uv: Vec2::ZERO,
+        })
+        .collect();
+    floor.add_vertices(&mut triangles, &mut vertices);
+
+    let lights = [
+        Light::directional(Vec3::new(1.0, -1.0, -0.5), Vec3::new(0.3, 0.4, 1.0), 0.4),
+        Light::point(Vec3::new(-2.5, 0.5, 1.5), Vec3::new(1.0, 0.3, 0.1), 6.0),
+        Light::spot(
+            Vec3::new(2.5, 4.0, 1.0),
+            Vec3::new(-0.3, -1.0, 0.0),
+            0.3,
+            0.5,
+            Vec3::ONE,
+            20.0,
+        ),
+    ];
+
+    let mut state = new_state(Camera {
+        transform: Transform::from_translation_rotation(
+            Vec3::new(0.0, 4.0, 8.0),
+            Quat::from_rotation_x(-0.45),
+        ),
+        ..Default::default()
+    });
+    let uniforms = |transform: &Transform| BlinnPhongUniforms {
+        transforms: Transforms::new(transform, &state.camera),
+        lights: &lights,
+        camera_position: state.camera.transform.translation,
+        ambient_light: Vec3::splat(0.05),
+        specular: Vec3::splat(0.5),
+        shininess: 32.0,
+    };
+    let cube_uniforms = uniforms(&Transform::from_rotation(Quat::from_rotation_y(0.6)));
+    let floor_uniforms = uniforms(&floor.transform);
+
+    let render_state = RenderState::new();
+    let mut binner = Binner::new(viewport(&state.framebuffer));
+    RenderMesh::from_mesh(cube).draw_mesh(
+        &render_state,
+        &cube_uniforms,
+        &BlinnPhongShader,
+        &BlinnPhongShader,
+        &mut binner,
+    );
+    RenderMesh::from_mesh(&floor).draw_mesh(
+        &render_state,
+        &floor_uniforms,
+        &BlinnPhongShader,
+        &BlinnPhongShader,
+        &mut binner,
+    );
+    binner.flush(&mut state.framebuffer);
+
+    check("lights", Image::from_framebuffer(&state.framebuffer));
+}
+
+fn colored_triangle(mesh: &mut Mesh, positions: [Vec3; 3], color: Vec3) {
+    let mut triangles = vec![glam::UVec3::new(0, 1, 2) + mesh.vertices.len() as u32];
+    let mut vertices = positions
+        .iter()
+        .zip([color, color * 0.6, color * 0.3])
+        .map(|(position, color)| Vertex {
+            position: position.extend(1.0),
+            normal: Vec3::Z,
+            color,
+            uv: Vec2::ZERO,
+        })
+        .collect();
+    mesh.add_vertices(&mut triangles, &mut vertices);
+}
+
+/// Triangles crossing the near and far planes, reaching far past the guard band, lying
+/// entirely behind the camera and thin slivers.
+fn clipping_scene() -> Mesh {
+    let mut mesh = Mesh::new();
+    // Ground plane running from behind the camera to beyond the far plane
+    colored_triangle(
+        &mut mesh,
+        [
+            Vec3::new(-4.0, -1.0, 10.0),
+            Vec3::new(4.0, -1.0, 10.0),
+            Vec3::new(0.0, -1.0, -500.0),
+        ],
+        Vec3::new(0.2, 0.8, 0.3),
+    );
+    // Wide enough to leave the guard band on both sides
+    colored_triangle(
+        &mut mesh,
+        [
+            Vec3::new(-5000.0, 0.5, -8.0),
+            Vec3::new(5000.0, 0.5, -8.0),
+            Vec3::new(0.0, 2.0, -8.0),
+        ],
+        Vec3::new(0.9, 0.3, 0.2),
+    );
+    // Crosses the near plane at a steep angle, right in front of the eye
+    colored_triangle(
+        &mut mesh,
+        [
+            Vec3::new(-0.5, -0.2, 0.5),
+            Vec3::new(0.6, 0.4, -3.0),
+            Vec3::new(-0.2, 0.6, -0.01),
+        ],
+        Vec3::new(0.2, 0.4, 1.0),
+    );
+    // Entirely behind the camera
+    colored_triangle(
+        &mut mesh,
+        [
+            Vec3::new(-1.0, -1.0, 2.0),
+            Vec3::new(1.0, -1.0, 2.0),
+            Vec3::new(0.0, 1.0, 2.0),
+        ],
+        Vec3::ONE,
+    );
+    // Slivers, thinner than a pixel over most of their length
+    for i in 0..6 {
+        let x = -1.5 + i as f32 * 0.6;
+        colored_triangle(
+            &mut mesh,
+            [
+                Vec3::new(x, -0.8, -4.0),
+                Vec3::new(x + 0.004 * i as f32, -0.8, -4.0),
+                Vec3::new(x + 0.3, 1.2, -4.0),
+            ],
+            Vec3::new(1.0, 0.9, 0.2),
+        );
+    }
+    mesh
+}
+
+#[test]
+fn clipping() {
+    let mesh = clipping_scene();
+    let mut state = new_state(Camera::default());
+    state.camera.aspect_ratio = state.framebuffer.width as f32 / state.framebuffer.height as f32;
+
+    let uniforms = Transforms::new(&mesh.transform, &state.camera);
+    let mut render_state = RenderState::new();
+    render_state.cull_mode = CullMode::None;
+
+    let mut binner = Binner::new(viewport(&state.framebuffer));
+    RenderMesh::from_mesh(&mesh).draw_mesh(
+        &render_state,
+        &uniforms,
+        &TransformShader,
+        &VertexColorShader,
+        &mut binner,
+    );
+    binner.flush(&mut state.framebuffer);
+
+    // The binned result has to match drawing the triangles one by one
+    let mut immediate = Framebuffer::new(state.framebuffer.width, state.framebuffer.height);
+    immediate.clear_color(to_argb8(255, 0, 0, 0));
+    RenderMesh::from_mesh(&mesh).draw_mesh_immediate(
+        &render_state,
+        &uniforms,
+        &TransformShader,
+        &VertexColorShader,
+        &mut immediate,
+    );
+    assert!(state.framebuffer.color == immediate.color);
+
+    check("clipping", Image::from_framebuffer(&state.framebuffer));
+}
+
+/// 4x4 texture with red increasing along u and green along v, so mirroring is visible.
+fn gradient_texture() -> Texture {
+    let mut texture = Texture {
+        width: 4,
+        height: 4,
+        data: (0..16)
+            .map(|i| to_argb8(255, (i % 4) as u8 * 64 + 32, (i / 4) as u8 * 64 + 32, 96))
+            .collect(),
+        depth: 4,
+        mips: Vec::new(),
+    };
+    texture.generate_mips();
+    texture
+}
+
+/// Quad spanning texture coordinates -1 to 2 on both axes.
+fn wrapped_quad(center: Vec2) -> Mesh {
+    let mut mesh = Mesh::new();
+    let mut triangles = vec![glam::UVec3::new(0, 1, 2), glam::UVec3::new(0, 2, 3)];
+    let mut vertices = [(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)]
+        .iter()
+        .map(|&(x, y)| Vertex {
+            position: (center + Vec2::new(x, y) * 0.9).extend(-7.0).extend(1.0),
+            normal: Vec3::Z,
+            color: Vec3::ONE,
+            uv: Vec2::new(x, y) * 1.5 + 0.5,
+        })
+        .collect();
+    mesh.add_vertices(&mut triangles, &mut vertices);
+    mesh
+}
+
+#[test]
+fn wrap_modes() {
+    let texture = gradient_texture();
+    let mut state = new_state(Camera::default());
+    let mut render_state = RenderState::new();
+    render_state.cull_mode = CullMode::None;
+
+    let quads = [
+        (WrapMode::Repeat, Vec2::new(-1.0, 1.0)),
+        (WrapMode::MirroredRepeat, Vec2::new(1.0, 1.0)),
+        (WrapMode::ClampToEdge, Vec2::new(-1.0, -1.0)),
+        (WrapMode::ClampToBorder, Vec2::new(1.0, -1.0)),
+    ];
+    // The binner holds on to the uniforms until it is flushed
+    let meshes: Vec<_> = quads
+        .iter()
+        .map(|&(_, center)| wrapped_quad(center))
+        .collect();
+    let uniforms: Vec<_> = quads
+        .iter()
+        .zip(&meshes)
+        .map(|(&(wrap, _), mesh)| BasicUniforms {
+            transforms: Transforms::new(&mesh.transform, &state.camera),
+            texture: Some(texture.view()),
+            sampler: Sampler {
+                filter: Filter::Nearest,
+                border_color: to_argb8(255, 255, 255, 255),
+                ..Sampler::with_wrap(wrap)
+            },
+            clear_color: Color::from_argb8(to_argb8(255, 0, 0, 0)),
+        })
+        .collect();
+
+    let mut binner = Binner::new(viewport(&state.framebuffer));
+    for (mesh, uniforms) in meshes.iter().zip(&uniforms) {
+        RenderMesh::from_mesh(mesh).draw_mesh(
+            &render_state,
+            uniforms,
+            &TransformShader,
+            &TextureShader,
+            &mut binner,
+        );
+    }
+    binner.flush(&mut state.framebuffer);
+
+    check("wrap_modes", Image::from_framebuffer(&state.framebuffer));
+}
+
+/// Quad with texture coordinates 0 to 1, facing the default camera. v runs down, like the rows
+/// of a texture.
+fn textured_quad(center: Vec2) -> Mesh {
+    let mut mesh = Mesh::new();
+    let mut triangles = vec![glam::UVec3::new(0, 1, 2), glam::UVec3::new(0, 2, 3)];
+    let mut vertices = [(0.0, 0.0), (1.0, 0.0), (1.0, 1.0), (0.0, 1.0)]
+        .iter()
+        .map(|&(u, v)| Vertex {
+            position: (center + (Vec2::new(u, v) - 0.5) * 1.8)
+                .extend(-7.0)
+                .extend(1.0),
+            normal: Vec3::Z,
+            color: Vec3::ONE,
+            uv: Vec2::new(u, 1.0 - v),
+        })
+        .collect();
+    mesh.add_vertices(&mut triangles, &mut vertices);
+    mesh
+}
+
+struct DepthViewUniforms<'a> {
+    transforms: Transforms,
+    depth: TextureView<'a, f32>,
+    /// Planes of the camera the depth was rendered with.
+    near: f32,
+    far: f32,
+}
+
+impl AsRef<Transforms> for DepthViewUniforms<'_> {
+    fn as_ref(&self) -> &Transforms {
+        &self.transforms
+    }
+}
+
+/// Shows a depth texture as its distance to the camera, brighter is closer.
+struct DepthViewShader;
+
+impl FragmentShader<DepthViewUniforms<'_>> for DepthViewShader {
+    type Varyings = BasicVaryings;
+
+    fn fragment(
+        &self,
+        uniforms: &DepthViewUniforms,
+        fragment: &Fragment<BasicVaryings>,
+    ) -> Option<u32> {
+        let sampler = Sampler {
+            filter: Filter::Nearest,
+            ..Sampler::with_wrap(WrapMode::ClampToEdge)
+        };
+        let depth = uniforms.depth.sample(
+            &sampler,
+            fragment.varyings.uv,
+            fragment.ddx.uv,
+            fragment.ddy.uv,
+        );
+        // Nothing was drawn where depth is infinite
+        let depth = depth.min(1.0);
+        let (near, far) = (uniforms.near, uniforms.far);
+        let distance = near * far / (far - depth * (far - near));
+        let brightness = ((8.0 - distance) / 4.0).clamp(0.0, 1.0) * 255.0;
+        Some(to_argb8(
+            255,
+            brightness as u8,
+            brightness as u8,
+            brightness as u8,
+        ))
+    }
+}
+
+#[test]
+fn render_to_texture() {
+    let path = manifest_dir().join("../window/assets/models/cube/cube.gltf");
+    let scene = load_gltf_scene(&path).expect("Failed to load cube.gltf");
+    let cube = &scene.models[0].primitives[0].mesh;
+    let camera = Camera {
+        transform: Transform::from_translation(Vec3::new(0.0, 0.0, 6.0)),
+        ..Default::default()
+    };
+    let rotation = Quat::from_euler(glam::EulerRot::YXZ, PI / 5.0, PI / 7.0, 0.0);
+    let cube_transforms = Transforms::from_model(Mat4::from_quat(rotation), &camera);
+
+    // The first pass renders the cube offscreen
+    let mut offscreen = Framebuffer::new(128, 128);
+    let mut pass = RenderPass::clear(&mut offscreen, to_argb8(255, 40, 40, 80));
+    pass.draw(
+        cube,
+        &RenderState::new(),
+        &cube_transforms,
+        &NormalShader,
+        &NormalShader,
+    );
+    pass.finish();
+
+    // The attachments are bound as they are, without copies
+    let color = offscreen.color_texture();
+    assert_eq!(color.data.as_ptr(), offscreen.color.as_ptr());
+    assert_eq!(
+        offscreen.depth_texture().data.as_ptr(),
+        offscreen.depth.as_ptr()
+    );
+
+    // The second pass shows its color and depth on two quads
+    let mut state = new_state(Camera::default());
+    let color_quad = textured_quad(Vec2::new(0.0, 1.0));
+    let depth_quad = textured_quad(Vec2::new(0.0, -1.0));
+    let color_uniforms = BasicUniforms {
+        transforms: Transforms::new(&color_quad.transform, &state.camera),
+        texture: Some(color),
+        sampler: Sampler::with_wrap(WrapMode::ClampToEdge),
+        clear_color: Color::from_argb8(to_argb8(255, 0, 0, 0)),
+    };
+    let depth_uniforms = DepthViewUniforms {
+        transforms: Transforms::new(&depth_quad.transform, &state.camera),
+        depth: offscreen.depth_texture(),
+        near: camera.near_plane,
+        far: camera.far_plane,
+    };
+
+    let render_state = RenderState::new();
+    let mut pass = RenderPass::clear(&mut state.framebuffer, to_argb8(255, 0, 0, 0));
+    pass.draw(
+        &color_quad,
+        &render_state,
+        &color_uniforms,
+        &TransformShader,
+        &TextureShader,
+    );
+    pass.draw(
+        &depth_quad,
+        &render_state,
+        &depth_uniforms,
+        &TransformShader,
+        &DepthViewShader,
+    );
+    pass.finish();
+
+    check(
+        "render_to_texture",
+        Image::from_framebuffer(&state.framebuffer),
+    );
+}
+
+#[test]
+fn post_effects() {
+    // Grading with the identity LUT leaves the image as it is
+    let lut = Lut::identity(17);
+    for rgb in [Vec3::ZERO, Vec3::ONE, Vec3::new(0.2, 0.5, 0.9)] {
+        assert!(lut.lookup(rgb).abs_diff_eq(rgb, 1e-5));
+    }
+    let mut framebuffer = render_cube(1);
+    let original = framebuffer.color.clone();
+    let mut chain = PostChain::new();
+    chain.push(ColorGrading { lut, strength: 1.0 });
+    chain.apply(&mut framebuffer);
+    assert_eq!(framebuffer.color, original);
+
+    // A warm grade, bloom, vignette and CRT, applied to a multisampled target
+    let mut framebuffer = render_cube(4);
+    let mut chain = PostChain::new();
+    chain.push(ColorGrading {
+        lut: Lut::from_fn(17, |rgb| {
+            let warm = rgb * Vec3::new(1.1, 1.0, 0.8);
+            warm.lerp(Vec3::splat(warm.dot(Vec3::new(0.299, 0.587, 0.114))), 0.3)
+        }),
+        strength: 1.0,
+    });
+    chain.push(Bloom {
+        threshold: 0.5,
+        ..Default::default()
+    });
+    chain.push(Vignette::default());
+    chain.push(Crt::default());
+    chain.apply(&mut framebuffer);
+
+    check("post_effects", Image::from_framebuffer(&framebuffer));
+}
+
+/// The shadow scene lit beyond the 8-bit range into an HDR target, then tone mapped with
+/// Reinhard, ACES filmic and exposure, stacked from top to bottom.
+#[test]
+fn tone_mapping() {
+    // Color textures are decoded from sRGB when loaded
+    let texture = Texture::from_rgba8(1, 1, &[188, 128, 0, 255]).to_linear(ColorSpace::Srgb);
+    assert!(texture.data[0].abs_diff_eq(Vec4::new(0.5029, 0.2159, 0.0, 1.0), 1e-3));
+
+    let mut scene = shadow_scene();
+    scene.lights.push(Light::directional(
+        Vec3::new(0.6, -1.0, 0.5),
+        Vec3::ONE,
+        12.0,
+    ));
+    let mut shadow = ShadowMap::new(&scene, 0, 512).unwrap();
+    shadow.render(&scene);
+
+    let mut state = new_state(shadow_camera());
+    let mut frame = Framebuffer::<Vec4>::new(state.framebuffer.width, state.framebuffer.height);
+    frame.clear_color(Vec4::W);
+    draw_scene(&scene, &state.camera, Some(&shadow), &mut frame);
+    // Nothing is clamped before tone mapping
+    assert!(frame.color.iter().any(|color| color.max_element() > 1.0));
+
+    let mut rgb = Vec::new();
+    for tone_mapping in [
+        ToneMapping::Reinhard,
+        ToneMapping::AcesFilmic,
+        ToneMapping::Exposure(1.5),
+    ] {
+        present(&frame, tone_mapping, &mut state.framebuffer);
+        rgb.extend(Image::from_framebuffer(&state.framebuffer).rgb);
+    }
+    check(
+        "tone_mapping",
+        Image {
+            width: WIDTH,
+            height: HEIGHT * 3,
+            rgb,
+        },
+    );
+}
@@ -0,0 +1,135 @@
+//! Loads the OBJ scene in `tests/obj/` and malformed variants of it.
+
+use std::{fs, path::PathBuf};
+
+use glam::{Vec2, Vec4};
+use rusterizer::obj_import::{load_obj_scene, ObjError};
+use shared::scene::Scene;
+
+fn obj_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/obj")
+}
+
+/// Writes the files to a directory of this test and process, loads `scene.obj` from it and
+/// removes the directory again.
+fn load_files(name: &str, files: &[(&str, &str)]) -> Result<Scene, ObjError> {
+    let dir = std::env::temp_dir().join(format!("rusterizer_obj_{}_{}", name, std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    for (file, contents) in files {
+        fs::write(dir.join(file), contents).unwrap();
+    }
+    let result = load_obj_scene(&dir.join("scene.obj"));
+    fs::remove_dir_all(&dir).unwrap();
+    result
+}
+
+fn parse_error_line(result: Result<Scene, ObjError>) -> usize {
+    match result {
+        Err(ObjError::Parse { line, .. }) => line,
+        Err(error) => panic!("Expected a parse error, got {}", error),
+        Ok(_) => panic!("Expected a parse error"),
+    }
+}
+
+#[test]
+fn scene() {
+    let scene = load_obj_scene(&obj_dir().join("scene.obj")).unwrap();
+
+    let names: Vec<_> = scene.models.iter().map(|m| m.name.as_deref()).collect();
+    assert_eq!(names, [Some("box"), Some("pentagon")]);
+    assert_eq!(scene.roots, [0, 1]);
+    assert_eq!(scene.textures.len(), 1);
+    assert_eq!(scene.materials[1].name.as_deref(), Some("red plastic"));
+
+    // Corners shared by faces with different normals or texture coordinates are split
+    let cube = &scene.models[0].primitives[0].mesh;
+    assert_eq!(cube.vertices.len(), 24);
+    assert_eq!(cube.triangles.len(), 12);
+    // OBJ texture coordinates start at the bottom of the image
+    assert_eq!(cube.vertices[0].uv, Vec2::new(0.0, 1.0));
+
+    // The pentagon is split in three, the triangle after it has another material
+    let primitives = &scene.models[1].primitives;
+    assert_eq!(primitives.len(), 2);
+    assert_eq!(primitives[0].material, Some(1));
+    assert_eq!(primitives[0].mesh.triangles.len(), 3);
+    assert_eq!(primitives[1].material, Some(0));
+    assert_eq!(primitives[1].mesh.vertices.len(), 3);
+}
+
+#[test]
+fn malformed_number() {
+    let result = load_files("number", &[("scene.obj", "v 0 0 0\nv 1 O 0\n")]);
+    assert_eq!(parse_error_line(result), 2);
+}
+
+#[test]
+fn vertex_colors() {
+    let obj = "v 0 0 0 0.5\nv 1 0 0 1 0 0\nv 0 1 0\nf 1 2 3\n";
+    let scene = load_files("colors", &[("scene.obj", obj)]).unwrap();
+    let vertices = &scene.models[0].primitives[0].mesh.vertices;
+    // The weight is dropped, positions always have w = 1
+    assert_eq!(vertices[0].position, Vec4::new(0.0, 0.0, 0.0, 1.0));
+    assert_eq!(vertices[0].color, Vec4::ONE);
+    assert_eq!(vertices[1].color, Vec4::new(1.0, 0.0, 0.0, 1.0));
+
+    let result = load_files("five_numbers", &[("scene.obj", "v 0 0 0 1 0\n")]);
+    assert_eq!(parse_error_line(result), 1);
+}
+
+#[test]
+fn index_out_of_range() {
+    let obj = "v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 3\nf 1 2 4\n";
+    assert_eq!(
+        parse_error_line(load_files("range", &[("scene.obj", obj)])),
+        5
+    );
+    let obj = "v 0 0 0\nv 1 0 0\nv 0 1 0\nf -1 -2 -4\n";
+    assert_eq!(
+        parse_error_line(load_files("negative", &[("scene.obj", obj)])),
+        4
+    );
+}
+
+#[test]
+fn degenerate_face() {
+    let result = load_files("face", &[("scene.obj", "v 0 0 0\nv 1 0 0\nf 1 2\n")]);
+    assert_eq!(parse_error_line(result), 3);
+}
+
+#[test]
+fn unknown_material() {
+    let files = [
+        ("scene.obj", "mtllib scene.mtl\nusemtl missing\n"),
+        ("scene.mtl", "newmtl present\nKd 1 1 1\n"),
+    ];
+    assert_eq!(parse_error_line(load_files("material", &files)), 2);
+}
+
+#[test]
+fn malformed_mtl() {
+    let files = [
+        ("scene.obj", "mtllib scene.mtl\n"),
+        ("scene.mtl", "newmtl broken\nKd 1 1\n"),
+    ];
+    match load_files("mtl", &files) {
+        Err(ObjError::Parse { path, line, .. }) => {
+            assert!(path.ends_with("scene.mtl"));
+            assert_eq!(line, 2);
+        }
+        _ => panic!("Expected a parse error in the MTL file"),
+    }
+}
+
+#[test]
+fn missing_files() {
+    let result = load_files("no_mtl", &[("scene.obj", "mtllib nothing.mtl\n")]);
+    assert!(matches!(result, Err(ObjError::Io { .. })));
+
+    let files = [
+        ("scene.obj", "mtllib scene.mtl\n"),
+        ("scene.mtl", "newmtl textured\nmap_Kd nothing.png\n"),
+    ];
+    let result = load_files("no_texture", &files);
+    assert!(matches!(result, Err(ObjError::Texture { .. })));
+}
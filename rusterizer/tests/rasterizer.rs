@@ -0,0 +1,155 @@
+//! Coverage and ordering guarantees of the rasterizer, checked without reference images.
+
+use glam::{Vec2, Vec3, Vec4};
+use rusterizer::{
+    binner::Binner,
+    geometry::{draw_triangle_clipped, CullMode, RenderState, Triangle, TriangleSetup},
+    shader::{Fragment, FragmentShader, VertexOutput},
+};
+use shared::{framebuffer::Framebuffer, to_argb8};
+
+/// Writes the interpolated color.
+struct ColorShader;
+
+impl FragmentShader<()> for ColorShader {
+    type Varyings = Vec3;
+
+    fn fragment(&self, _uniforms: &(), fragment: &Fragment<Vec3>) -> Option<u32> {
+        let color = fragment.varyings * 255.0;
+        Some(to_argb8(255, color.x as u8, color.y as u8, color.z as u8))
+    }
+}
+
+/// Small linear congruential generator, so the triangles are the same on every run.
+struct Random(u64);
+
+impl Random {
+    fn next(&mut self) -> f32 {
+        self.0 = self
+            .0
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        (self.0 >> 40) as f32 / (1u64 << 24) as f32
+    }
+
+    fn vertex(&mut self) -> VertexOutput<Vec3> {
+        // Partly outside the viewport, so some triangles get clipped by the rasterizer's bounds
+        let position = Vec4::new(
+            self.next() * 2.4 - 1.2,
+            self.next() * 2.4 - 1.2,
+            self.next(),
+            1.0,
+        );
+        let color = Vec3::new(self.next(), self.next(), self.next());
+        VertexOutput {
+            position,
+            varyings: color,
+        }
+    }
+}
+
+fn random_triangles(count: usize) -> Vec<Triangle<Vec3>> {
+    let mut random = Random(7);
+    (0..count)
+        .map(|_| Triangle::new(random.vertex(), random.vertex(), random.vertex()))
+        .collect()
+}
+
+#[test]
+fn binned_matches_immediate() {
+    let triangles = random_triangles(64);
+    let mut render_state = RenderState::new();
+    render_state.cull_mode = CullMode::None;
+
+    // Neither side is a multiple of the tile size
+    for samples in [1, 4] {
+        let mut binned = Framebuffer::multisampled(150, 100, samples);
+        let mut binner = Binner::new(Vec2::new(150.0, 100.0));
+        binner.submit(&render_state, &(), &ColorShader, &triangles);
+        binner.flush(&mut binned);
+
+        let mut immediate = Framebuffer::multisampled(150, 100, samples);
+        for triangle in &triangles {
+            draw_triangle_clipped(triangle, &render_state, &(), &ColorShader, &mut immediate);
+        }
+
+        assert!(binned.color == immediate.color);
+        assert!(binned.depth == immediate.depth);
+    }
+}
+
+#[test]
+#[should_panic]
+fn flush_into_mismatched_target() {
+    let mut render_state = RenderState::new();
+    render_state.cull_mode = CullMode::None;
+    let mut binner = Binner::new(Vec2::new(150.0, 100.0));
+    binner.submit(&render_state, &(), &ColorShader, &random_triangles(4));
+    binner.flush(&mut Framebuffer::new(100, 150));
+}
+
+/// Clip-space vertex at a screen position of the viewport.
+fn screen_vertex(position: Vec2, viewport: Vec2) -> VertexOutput<Vec3> {
+    let ndc = Vec2::new(position.x / viewport.x, 1.0 - position.y / viewport.y) * 2.0 - 1.0;
+    VertexOutput {
+        position: ndc.extend(0.5).extend(1.0),
+        varyings: Vec3::ZERO,
+    }
+}
+
+#[test]
+fn shared_edges_cover_once() {
+    let viewport = Vec2::new(40.0, 24.0);
+    let mut render_state = RenderState::new();
+    render_state.cull_mode = CullMode::None;
+
+    // A fan around the center reaching past the viewport, so every sample is inside exactly
+    // one of its triangles. Centers on a pixel center or corner put the horizontal, vertical
+    // and diagonal edges right through samples.
+    let outer = [
+        (-8.0, -8.0),
+        (20.0, -8.0),
+        (48.0, -8.0),
+        (48.0, 12.0),
+        (48.0, 32.0),
+        (20.0, 32.0),
+        (-8.0, 32.0),
+        (-8.0, 12.0),
+    ]
+    .map(|(x, y)| Vec2::new(x, y));
+    for center in [
+        Vec2::new(20.5, 12.5),
+        Vec2::new(16.0, 8.0),
+        Vec2::new(17.3, 9.7),
+    ] {
+        for samples in [1, 4] {
+            let mut counts = vec![0; 40 * 24 * samples];
+            for i in 0..outer.len() {
+                let (a, b) = (outer[i], outer[(i + 1) % outer.len()]);
+                // Alternate the winding, the fill rule must not depend on it
+                let (a, b) = if i % 2 == 0 { (a, b) } else { (b, a) };
+                let triangle = Triangle::new(
+                    screen_vertex(center, viewport),
+                    screen_vertex(a, viewport),
+                    screen_vertex(b, viewport),
+                );
+                let setup = TriangleSetup::new(&triangle, viewport, &render_state).unwrap();
+                let (columns, rows) = setup.pixel_range();
+                setup.rasterize(columns, rows, samples, |coverage| {
+                    let first = (coverage.y * 40 + coverage.x) * samples;
+                    for sample in 0..samples {
+                        if coverage.mask & (1 << sample) != 0 {
+                            counts[first + sample] += 1;
+                        }
+                    }
+                });
+            }
+            assert!(
+                counts.iter().all(|&count| count == 1),
+                "Samples drawn twice or not at all around {} with {} samples",
+                center,
+                samples
+            );
+        }
+    }
+}
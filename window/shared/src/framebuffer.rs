@@ -0,0 +1,129 @@
+use std::borrow::Cow;
+
+use glam::Vec4;
+
+use crate::texture::{Texel, TextureView};
+
+/// Most samples per pixel a framebuffer can have.
+pub const MAX_SAMPLES: usize = 8;
+
+/// Render target with a color and a depth attachment.
+///
+/// Color is 8-bit ARGB by default, displayed as is. HDR targets store linear RGBA as `Vec4`
+/// instead, which has to be tone mapped before it can be displayed.
+///
+/// Pixels are stored row by row at `y * width + x`, the same layout as `Texture`. With
+/// multisampling, every pixel stores `samples` consecutive color and depth values.
+///
+/// Depth goes from 0 at the near plane to 1 at the far plane. Depth-only framebuffers have
+/// no color attachment, their `color` is empty.
+pub struct Framebuffer<C = u32> {
+    pub width: usize,
+    pub height: usize,
+    pub samples: usize,
+    pub color: Vec<C>,
+    pub depth: Vec<f32>,
+}
+
+impl<C: Texel> Framebuffer<C> {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self::multisampled(width, height, 1)
+    }
+
+    /// Creates a framebuffer with a power of two samples per pixel, up to `MAX_SAMPLES`.
+    pub fn multisampled(width: usize, height: usize, samples: usize) -> Self {
+        assert!(
+            samples.is_power_of_two() && samples <= MAX_SAMPLES,
+            "Unsupported sample count: {}",
+            samples
+        );
+        Self {
+            width,
+            height,
+            samples,
+            color: vec![C::default(); width * height * samples],
+            depth: vec![f32::INFINITY; width * height * samples],
+        }
+    }
+
+    /// Creates a single sampled framebuffer without color, for depth-only passes such as
+    /// shadow maps.
+    pub fn depth_only(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            samples: 1,
+            color: Vec::new(),
+            depth: vec![f32::INFINITY; width * height],
+        }
+    }
+
+    pub fn has_color(&self) -> bool {
+        !self.color.is_empty()
+    }
+
+    /// Index of the pixel, multiply by `samples` for the index of its first sample.
+    pub fn index(&self, x: usize, y: usize) -> usize {
+        y * self.width + x
+    }
+
+    pub fn set_pixel(&mut self, x: usize, y: usize, color: C) {
+        if x < self.width && y < self.height && self.has_color() {
+            let first = self.index(x, y) * self.samples;
+            self.color[first..first + self.samples].fill(color);
+        }
+    }
+
+    pub fn clear_color(&mut self, color: C) {
+        self.color.fill(color);
+    }
+
+    pub fn clear_depth(&mut self) {
+        self.depth.fill(f32::INFINITY);
+    }
+
+    /// The color attachment as a texture, without copying it. Multisampled framebuffers have
+    /// to be resolved into a single sampled one first.
+    pub fn color_texture(&self) -> TextureView<'_, C> {
+        assert!(self.has_color(), "Framebuffer has no color attachment");
+        self.attachment(&self.color)
+    }
+
+    /// The depth attachment as a texture, without copying it. Depth goes from 0 at the near
+    /// plane to 1 at the far plane, and is infinite where nothing was drawn.
+    pub fn depth_texture(&self) -> TextureView<'_, f32> {
+        self.attachment(&self.depth)
+    }
+
+    fn attachment<'a, T>(&self, data: &'a [T]) -> TextureView<'a, T> {
+        assert_eq!(
+            self.samples, 1,
+            "Multisampled attachments can't be sampled, resolve them first"
+        );
+        TextureView {
+            width: self.width,
+            height: self.height,
+            data,
+            mips: &[],
+        }
+    }
+
+    /// Averages the samples of every pixel. Single sampled color is returned as is.
+    pub fn resolve(&self) -> Cow<'_, [C]> {
+        if self.samples == 1 {
+            return Cow::Borrowed(&self.color);
+        }
+
+        let mut resolved = vec![C::default(); self.width * self.height];
+        self.resolve_into(&mut resolved);
+        Cow::Owned(resolved)
+    }
+
+    /// Averages the samples of every pixel into `color`, which holds one color per pixel.
+    pub fn resolve_into(&self, color: &mut [C]) {
+        for (resolved, samples) in color.iter_mut().zip(self.color.chunks_exact(self.samples)) {
+            let sum: Vec4 = samples.iter().map(|color| color.to_vec4()).sum();
+            *resolved = C::from_vec4(sum / self.samples as f32);
+        }
+    }
+}
@@ -1,48 +1,79 @@
 pub mod camera;
+pub mod framebuffer;
+pub mod light;
+pub mod material;
 pub mod mesh;
+pub mod scene;
 pub mod texture;
 pub mod transform;
-use glam::Vec2;
+use std::{any::Any, path::PathBuf};
 
 use crate::camera::*;
+use crate::framebuffer::*;
 use crate::mesh::*;
 use crate::texture::*;
 use crate::transform::*;
-
-pub const WIDTH: usize = 600;
-pub const HEIGHT: usize = 600;
-
-pub type FnPtrDraw = fn(u16, u16, u32);
+use glam::{Vec3, Vec4};
 
 pub struct State {
     pub version: u32,
     pub time_passed: f32,
-    pub draw_fn: FnPtrDraw,
+    pub framebuffer: Framebuffer,
+    /// Linear HDR color the library may render into before tone mapping it to `framebuffer`,
+    /// with the same size and samples.
+    pub hdr_frame: Framebuffer<Vec4>,
     pub meshes: Vec<Mesh>,
-    pub textures: Vec<Texture>,
+    /// Linear textures, decoded from sRGB when loaded.
+    pub textures: Vec<Texture<Vec4>>,
     pub camera: Camera,
     pub should_clear: bool,
     pub clear_color: u32,
+    /// Directory the library loads its assets from, relative to the working directory.
+    pub asset_root: PathBuf,
+    /// Whatever the library keeps between frames. It may refer to the library's code, so the
+    /// host drops it before unloading the library.
+    pub renderer: Option<Box<dyn Any>>,
 }
 
 impl State {
+    /// State with a `width` x `height` framebuffer and a default camera matching its aspect
+    /// ratio, before any scene is loaded.
+    pub fn new(width: usize, height: usize) -> Self {
+        let mut state = Self {
+            version: 1,
+            time_passed: 0.0,
+            framebuffer: Framebuffer::new(0, 0),
+            hdr_frame: Framebuffer::new(0, 0),
+            meshes: Vec::new(),
+            textures: Vec::new(),
+            camera: Camera::default(),
+            should_clear: true,
+            clear_color: 0x00,
+            asset_root: PathBuf::new(),
+            renderer: None,
+        };
+        state.resize(width, height);
+        state
+    }
     pub fn finalize(&self) {
         println!("LIB ACTIVE!");
     }
-    pub fn draw(&self, x: u16, y: u16, color: u32) {
-        (self.draw_fn)(x, y, color);
-    }
     pub fn set_clear_color(&mut self, color: u32) {
         self.clear_color = color;
     }
-}
-
-pub fn index_to_coords(p: usize) -> Vec2 {
-    Vec2::new(p as f32 / WIDTH as f32, p as f32 % WIDTH as f32)
-}
-
-pub fn coords_to_index(coord: Vec2) -> usize {
-    coord.x as usize * WIDTH + coord.y as usize
+    /// Reallocates the framebuffers and matches the camera's aspect ratio to them.
+    pub fn resize(&mut self, width: usize, height: usize) {
+        let samples = self.framebuffer.samples;
+        self.framebuffer = Framebuffer::multisampled(width, height, samples);
+        self.hdr_frame = Framebuffer::multisampled(width, height, samples);
+        self.camera.aspect_ratio = width as f32 / height as f32;
+    }
+    /// Reallocates the framebuffers with the given number of samples per pixel.
+    pub fn set_samples(&mut self, samples: usize) {
+        let (width, height) = (self.framebuffer.width, self.framebuffer.height);
+        self.framebuffer = Framebuffer::multisampled(width, height, samples);
+        self.hdr_frame = Framebuffer::multisampled(width, height, samples);
+    }
 }
 
 pub fn to_argb8(a: u8, r: u8, g: u8, b: u8) -> u32 {
@@ -52,3 +83,28 @@ pub fn to_argb8(a: u8, r: u8, g: u8, b: u8) -> u32 {
     argb = (argb << 8) + b as u32;
     argb
 }
+
+/// Decodes an sRGB encoded color to linear light.
+pub fn srgb_to_linear(color: Vec3) -> Vec3 {
+    let decode = |c: f32| {
+        if c <= 0.04045 {
+            c / 12.92
+        } else {
+            ((c + 0.055) / 1.055).powf(2.4)
+        }
+    };
+    Vec3::new(decode(color.x), decode(color.y), decode(color.z))
+}
+
+/// Encodes a linear color as sRGB, inputs outside 0 to 1 are clamped.
+pub fn linear_to_srgb(color: Vec3) -> Vec3 {
+    let encode = |c: f32| {
+        let c = c.clamp(0.0, 1.0);
+        if c <= 0.0031308 {
+            c * 12.92
+        } else {
+            1.055 * c.powf(1.0 / 2.4) - 0.055
+        }
+    };
+    Vec3::new(encode(color.x), encode(color.y), encode(color.z))
+}
@@ -0,0 +1,140 @@
+use glam::Vec3;
+
+/// Falloff of a light with the distance `d` to it: `1 / (constant + linear * d + quadratic * d²)`.
+#[derive(Debug, Copy, Clone)]
+pub struct Attenuation {
+    pub constant: f32,
+    pub linear: f32,
+    pub quadratic: f32,
+}
+
+impl Attenuation {
+    pub fn at(&self, distance: f32) -> f32 {
+        1.0 / (self.constant + self.linear * distance + self.quadratic * distance * distance)
+            .max(f32::EPSILON)
+    }
+}
+
+impl Default for Attenuation {
+    /// Inverse square falloff, kept finite close to the light.
+    fn default() -> Self {
+        Self {
+            constant: 1.0,
+            linear: 0.0,
+            quadratic: 1.0,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub enum LightKind {
+    /// Infinitely far away, lighting everything from the same direction.
+    Directional {
+        /// Direction the light travels in.
+        direction: Vec3,
+    },
+    /// Shining in all directions from a position.
+    Point {
+        position: Vec3,
+        attenuation: Attenuation,
+    },
+    /// Shining in a cone from a position. The light fades out between the inner and outer
+    /// angle, both measured from the direction.
+    Spot {
+        position: Vec3,
+        direction: Vec3,
+        inner_angle: f32,
+        outer_angle: f32,
+        attenuation: Attenuation,
+    },
+}
+
+/// Light source in world space, its color is linear.
+#[derive(Debug, Copy, Clone)]
+pub struct Light {
+    pub kind: LightKind,
+    pub color: Vec3,
+    pub intensity: f32,
+}
+
+impl Light {
+    pub fn directional(direction: Vec3, color: Vec3, intensity: f32) -> Self {
+        Self {
+            kind: LightKind::Directional {
+                direction: direction.normalize(),
+            },
+            color,
+            intensity,
+        }
+    }
+
+    pub fn point(position: Vec3, color: Vec3, intensity: f32) -> Self {
+        Self {
+            kind: LightKind::Point {
+                position,
+                attenuation: Attenuation::default(),
+            },
+            color,
+            intensity,
+        }
+    }
+
+    pub fn spot(
+        position: Vec3,
+        direction: Vec3,
+        inner_angle: f32,
+        outer_angle: f32,
+        color: Vec3,
+        intensity: f32,
+    ) -> Self {
+        Self {
+            kind: LightKind::Spot {
+                position,
+                direction: direction.normalize(),
+                inner_angle,
+                outer_angle,
+                attenuation: Attenuation::default(),
+            },
+            color,
+            intensity,
+        }
+    }
+
+    /// Direction from `position` towards the light, and the light arriving at `position`.
+    pub fn illuminate(&self, position: Vec3) -> (Vec3, Vec3) {
+        let radiance = self.color * self.intensity;
+        match self.kind {
+            LightKind::Directional { direction } => (-direction, radiance),
+            LightKind::Point {
+                position: light,
+                attenuation,
+            } => {
+                let to_light = light - position;
+                let distance = to_light.length();
+                (
+                    to_light / distance.max(f32::EPSILON),
+                    radiance * attenuation.at(distance),
+                )
+            }
+            LightKind::Spot {
+                position: light,
+                direction,
+                inner_angle,
+                outer_angle,
+                attenuation,
+            } => {
+                let to_light = light - position;
+                let distance = to_light.length();
+                let l = to_light / distance.max(f32::EPSILON);
+
+                // Smooth falloff from the inner to the outer cone
+                let (inner, outer) = (inner_angle.cos(), outer_angle.cos());
+                let t = ((-l).dot(direction) - outer) / (inner - outer).max(f32::EPSILON);
+                let t = t.clamp(0.0, 1.0);
+                let cone = t * t * (3.0 - 2.0 * t);
+
+                (l, radiance * attenuation.at(distance) * cone)
+            }
+        }
+    }
+}
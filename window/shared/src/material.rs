@@ -0,0 +1,84 @@
+use glam::{Vec3, Vec4};
+
+use crate::texture::Sampler;
+
+/// Reference from a material to one of the scene's textures.
+#[derive(Debug, Copy, Clone)]
+pub struct MaterialTexture {
+    /// Index into `Scene::textures`.
+    pub texture: usize,
+    pub sampler: Sampler,
+    /// Which texture coordinate set to sample with, 0 for `uv` and 1 for `uv1`.
+    pub tex_coord: usize,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum AlphaMode {
+    /// Alpha is ignored.
+    Opaque,
+    /// Fragments with an alpha below `Material::alpha_cutoff` are discarded.
+    Mask,
+    /// The surface is blended over what is behind it by its alpha, after the opaque surfaces.
+    Blend,
+}
+
+/// Surface description shared by the primitives that reference it, following glTF's
+/// metallic-roughness model. Colors are linear.
+#[derive(Debug, Clone)]
+pub struct Material {
+    pub name: Option<String>,
+    /// Multiplied with the base color texture.
+    pub base_color_factor: Vec4,
+    pub base_color_texture: Option<MaterialTexture>,
+    pub metallic_factor: f32,
+    pub roughness_factor: f32,
+    /// Roughness in the green channel and metalness in the blue channel, multiplied with
+    /// the factors.
+    pub metallic_roughness_texture: Option<MaterialTexture>,
+    /// Tangent space normals.
+    pub normal_texture: Option<MaterialTexture>,
+    /// Scales the x and y of the normals read from the normal texture.
+    pub normal_scale: f32,
+    /// Ambient occlusion in the red channel.
+    pub occlusion_texture: Option<MaterialTexture>,
+    /// How much of the occlusion texture is applied, from 0 to 1.
+    pub occlusion_strength: f32,
+    /// Multiplied with the emissive texture.
+    pub emissive_factor: Vec3,
+    pub emissive_texture: Option<MaterialTexture>,
+    pub alpha_mode: AlphaMode,
+    pub alpha_cutoff: f32,
+    /// Back faces are rendered, instead of culled.
+    pub double_sided: bool,
+    /// Surfaces are darkened where a shadow map sees something between them and the light.
+    /// Shadows are opt-in, every surface casts them.
+    pub receive_shadows: bool,
+}
+
+impl Material {
+    /// Untextured white, used by primitives without a material.
+    pub const DEFAULT: Self = Self {
+        name: None,
+        base_color_factor: Vec4::ONE,
+        base_color_texture: None,
+        metallic_factor: 1.0,
+        roughness_factor: 1.0,
+        metallic_roughness_texture: None,
+        normal_texture: None,
+        normal_scale: 1.0,
+        occlusion_texture: None,
+        occlusion_strength: 1.0,
+        emissive_factor: Vec3::ZERO,
+        emissive_texture: None,
+        alpha_mode: AlphaMode::Opaque,
+        alpha_cutoff: 0.5,
+        double_sided: false,
+        receive_shadows: false,
+    };
+}
+
+impl Default for Material {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
@@ -1,12 +1,23 @@
 use std::ops::{Add, Mul, MulAssign, Sub};
 
-use glam::{UVec3, Vec2, Vec3, Vec4};
+use glam::{UVec3, UVec4, Vec2, Vec3, Vec4};
 
 use crate::transform::Transform;
 
+/// Per-vertex data of a mesh. Custom vertex structs implement this to carry whatever
+/// attributes their vertex shaders need.
+pub trait MeshVertex: Copy + Send + Sync {
+    /// Object-space position, with w = 1.
+    fn position(&self) -> Vec4;
+    /// Object-space normal.
+    fn normal(&self) -> Vec3;
+    fn set_normal(&mut self, normal: Vec3);
+}
+
 #[derive(Debug, Clone, Copy)]
 pub struct Vertex {
     pub position: Vec4,
+    pub normal: Vec3,
     pub color: Vec3,
     pub uv: Vec2,
 }
@@ -16,10 +27,12 @@ impl Mul<f32> for Vertex {
 
     fn mul(self, rhs: f32) -> Self {
         let position = self.position * rhs;
+        let normal = self.normal * rhs;
         let color = self.color * rhs;
         let uv = self.uv * rhs;
         Self {
             position,
+            normal,
             color,
             uv,
         }
@@ -29,6 +42,7 @@ impl Mul<f32> for Vertex {
 impl MulAssign<f32> for Vertex {
     fn mul_assign(&mut self, rhs: f32) {
         self.position *= rhs;
+        self.normal *= rhs;
         self.color *= rhs;
         self.uv *= rhs;
     }
@@ -39,10 +53,12 @@ impl Add for Vertex {
 
     fn add(self, rhs: Self) -> Self {
         let position = self.position + rhs.position;
+        let normal = self.normal + rhs.normal;
         let color = self.color + rhs.color;
         let uv = self.uv + rhs.uv;
         Self {
             position,
+            normal,
             color,
             uv,
         }
@@ -54,23 +70,82 @@ impl Sub for Vertex {
 
     fn sub(self, rhs: Self) -> Self {
         let position = self.position - rhs.position;
+        let normal = self.normal - rhs.normal;
         let color = self.color - rhs.color;
         let uv = self.uv - rhs.uv;
         Self {
             position,
+            normal,
             color,
             uv,
         }
     }
 }
 
-pub struct Mesh {
+impl MeshVertex for Vertex {
+    fn position(&self) -> Vec4 {
+        self.position
+    }
+
+    fn normal(&self) -> Vec3 {
+        self.normal
+    }
+
+    fn set_normal(&mut self, normal: Vec3) {
+        self.normal = normal;
+    }
+}
+
+/// Vertex with every attribute glTF defines, missing attributes are left at their default.
+#[derive(Debug, Clone, Copy)]
+pub struct GltfVertex {
+    pub position: Vec4,
+    pub normal: Vec3,
+    /// Tangent with the bitangent sign in w.
+    pub tangent: Vec4,
+    pub uv: Vec2,
+    pub uv1: Vec2,
+    pub color: Vec4,
+    pub joints: UVec4,
+    pub weights: Vec4,
+}
+
+impl Default for GltfVertex {
+    fn default() -> Self {
+        Self {
+            position: Vec4::W,
+            normal: Vec3::Z,
+            tangent: Vec4::new(1.0, 0.0, 0.0, 1.0),
+            uv: Vec2::ZERO,
+            uv1: Vec2::ZERO,
+            color: Vec4::ONE,
+            joints: UVec4::ZERO,
+            weights: Vec4::ZERO,
+        }
+    }
+}
+
+impl MeshVertex for GltfVertex {
+    fn position(&self) -> Vec4 {
+        self.position
+    }
+
+    fn normal(&self) -> Vec3 {
+        self.normal
+    }
+
+    fn set_normal(&mut self, normal: Vec3) {
+        self.normal = normal;
+    }
+}
+
+pub struct Mesh<V = Vertex> {
     pub triangles: Vec<UVec3>,
-    pub vertices: Vec<Vertex>,
+    pub vertices: Vec<V>,
     pub transform: Transform,
 }
 
-impl Mesh {
+impl<V> Mesh<V> {
     pub fn new() -> Self {
         Self {
             triangles: Vec::new(),
@@ -83,11 +158,11 @@ impl Mesh {
         &self.triangles
     }
 
-    pub fn vertices(&self) -> &Vec<Vertex> {
+    pub fn vertices(&self) -> &Vec<V> {
         &self.vertices
     }
 
-    pub fn get_triangle_vertices(&self, triangle: UVec3) -> [&Vertex; 3] {
+    pub fn get_triangle_vertices(&self, triangle: UVec3) -> [&V; 3] {
         [
             &self.vertices[triangle.x as usize],
             &self.vertices[triangle.y as usize],
@@ -95,13 +170,94 @@ impl Mesh {
         ]
     }
 
-    pub fn add_vertices(&mut self, triangles: &mut Vec<UVec3>, vertices: &mut Vec<Vertex>) {
+    pub fn add_vertices(&mut self, triangles: &mut Vec<UVec3>, vertices: &mut Vec<V>) {
         self.triangles.append(triangles);
         self.vertices.append(vertices);
     }
 }
 
-impl Default for Mesh {
+impl<V: MeshVertex> Mesh<V> {
+    /// Replaces the normals with smooth ones, averaging the normals of the triangles around
+    /// each vertex weighted by their area. Vertices that are split along hard edges keep
+    /// those edges.
+    pub fn generate_normals(&mut self) {
+        let mut normals = vec![Vec3::ZERO; self.vertices.len()];
+        for triangle in &self.triangles {
+            let [p0, p1, p2] = self
+                .get_triangle_vertices(*triangle)
+                .map(|vertex| vertex.position().truncate());
+            // Twice the triangle's area in length
+            let normal = (p1 - p0).cross(p2 - p0);
+            for index in triangle.to_array() {
+                normals[index as usize] += normal;
+            }
+        }
+        for (vertex, normal) in self.vertices.iter_mut().zip(normals) {
+            vertex.set_normal(normal.normalize_or_zero());
+        }
+    }
+}
+
+impl Mesh<GltfVertex> {
+    /// Generates tangents from the texture coordinates, in the spirit of MikkTSpace: the
+    /// tangents of the triangles around a vertex are weighted by the triangle's angle at the
+    /// vertex, made orthogonal to the normal and given the handedness of the bitangent.
+    ///
+    /// Like glTF, the bitangent is `normal.cross(tangent) * w` and points towards decreasing
+    /// v, up in the image.
+    pub fn generate_tangents(&mut self) {
+        let mut tangents = vec![Vec3::ZERO; self.vertices.len()];
+        let mut bitangents = vec![Vec3::ZERO; self.vertices.len()];
+
+        for triangle in &self.triangles {
+            let indices = triangle.to_array().map(|index| index as usize);
+            let [v0, v1, v2] = indices.map(|index| self.vertices[index]);
+            let (e1, e2) = (
+                (v1.position - v0.position).truncate(),
+                (v2.position - v0.position).truncate(),
+            );
+            let (duv1, duv2) = (v1.uv - v0.uv, v2.uv - v0.uv);
+            let determinant = duv1.x * duv2.y - duv2.x * duv1.y;
+            if determinant.abs() <= f32::EPSILON {
+                continue;
+            }
+            // Derivatives of the position along u and v
+            let tangent = (e1 * duv2.y - e2 * duv1.y) / determinant;
+            let bitangent = (e2 * duv1.x - e1 * duv2.x) / determinant;
+
+            let positions = [v0, v1, v2].map(|vertex| vertex.position.truncate());
+            for (corner, &index) in indices.iter().enumerate() {
+                let p = positions[corner];
+                let a = (positions[(corner + 1) % 3] - p).normalize_or_zero();
+                let b = (positions[(corner + 2) % 3] - p).normalize_or_zero();
+                let angle = a.dot(b).clamp(-1.0, 1.0).acos();
+                tangents[index] += tangent * angle;
+                bitangents[index] += bitangent * angle;
+            }
+        }
+
+        for ((vertex, tangent), bitangent) in self.vertices.iter_mut().zip(tangents).zip(bitangents)
+        {
+            let normal = vertex.normal.normalize_or_zero();
+            if normal == Vec3::ZERO {
+                continue;
+            }
+            // Without a texture mapping to follow any direction along the surface will do
+            let mut tangent = (tangent - normal * normal.dot(tangent)).normalize_or_zero();
+            if tangent == Vec3::ZERO {
+                tangent = normal.any_orthonormal_vector();
+            }
+            let handedness = if normal.cross(tangent).dot(bitangent) > 0.0 {
+                -1.0
+            } else {
+                1.0
+            };
+            vertex.tangent = tangent.extend(handedness);
+        }
+    }
+}
+
+impl<V> Default for Mesh<V> {
     fn default() -> Self {
         Self::new()
     }
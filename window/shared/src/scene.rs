@@ -0,0 +1,75 @@
+use glam::{Mat4, Vec3, Vec4};
+
+use crate::light::Light;
+use crate::material::Material;
+use crate::mesh::{GltfVertex, Mesh};
+use crate::texture::Texture;
+use crate::transform::Transform;
+
+/// Part of a model drawn with a single material.
+pub struct Primitive {
+    pub mesh: Mesh<GltfVertex>,
+    /// Index into `Scene::materials`, `None` for the default material.
+    pub material: Option<usize>,
+}
+
+/// Geometry that nodes can place in the scene, possibly more than once.
+pub struct Model {
+    pub name: Option<String>,
+    pub primitives: Vec<Primitive>,
+}
+
+pub struct Node {
+    pub name: Option<String>,
+    /// Transform relative to the parent node.
+    pub transform: Transform,
+    /// Index into `Scene::models`.
+    pub model: Option<usize>,
+    /// Indices into `Scene::nodes`. No node may be its own ancestor.
+    pub children: Vec<usize>,
+}
+
+/// Node hierarchy with the models, materials and textures it references.
+#[derive(Default)]
+pub struct Scene {
+    pub nodes: Vec<Node>,
+    /// Indices of the nodes without a parent.
+    pub roots: Vec<usize>,
+    pub models: Vec<Model>,
+    pub materials: Vec<Material>,
+    /// Linear textures, color textures are decoded from sRGB when loaded.
+    pub textures: Vec<Texture<Vec4>>,
+    pub lights: Vec<Light>,
+    /// Linear light reaching every surface from all directions.
+    pub ambient_light: Vec3,
+}
+
+impl Scene {
+    /// Every model placed by a node, with the node's world matrix.
+    pub fn instances(&self) -> Vec<(Mat4, &Model)> {
+        let mut instances = Vec::new();
+        let mut stack: Vec<(usize, Mat4)> = self
+            .roots
+            .iter()
+            .map(|&root| (root, Mat4::IDENTITY))
+            .collect();
+
+        while let Some((index, parent)) = stack.pop() {
+            let node = &self.nodes[index];
+            let world = parent * node.transform.local();
+            if let Some(model) = node.model {
+                instances.push((world, &self.models[model]));
+            }
+            stack.extend(node.children.iter().map(|&child| (child, world)));
+        }
+        instances
+    }
+
+    /// Material of a primitive, the default material if it has none.
+    pub fn material(&self, primitive: &Primitive) -> &Material {
+        match primitive.material {
+            Some(material) => &self.materials[material],
+            None => &Material::DEFAULT,
+        }
+    }
+}
@@ -1,73 +1,457 @@
-use glam::Vec2;
+use glam::{Vec2, Vec3, Vec4};
 use stb_image;
 use std::path::Path;
 
-use crate::to_argb8;
+use crate::{srgb_to_linear, to_argb8};
 
-pub struct Texture {
+/// Texel formats that can be sampled. Texels are filtered as RGBA in a `Vec4`, red in x to
+/// alpha in w, with the channels as the format stores them: 8-bit channels in 0 to 1, still
+/// sRGB encoded if they were, and formats without some channels filling green and blue with
+/// 0 and alpha with 1.
+pub trait Texel: Copy + Default + Send + Sync + 'static {
+    /// RGBA of the texel, as it is filtered.
+    fn to_vec4(self) -> Vec4;
+    /// Stores filtered RGBA, dropping the channels the format doesn't have.
+    fn from_vec4(rgba: Vec4) -> Self;
+    /// RGBA of a sampler's ARGB border color, as the format would store it.
+    fn border(argb: u32) -> Vec4;
+}
+
+/// 8-bit ARGB color.
+impl Texel for u32 {
+    fn to_vec4(self) -> Vec4 {
+        argb_to_rgba(self)
+    }
+
+    fn from_vec4(rgba: Vec4) -> Self {
+        rgba_to_argb(rgba)
+    }
+
+    fn border(argb: u32) -> Vec4 {
+        argb_to_rgba(argb)
+    }
+}
+
+/// Single channel, such as depth, in red. The border is the border color's red channel.
+impl Texel for f32 {
+    fn to_vec4(self) -> Vec4 {
+        Vec4::new(self, 0.0, 0.0, 1.0)
+    }
+
+    fn from_vec4(rgba: Vec4) -> Self {
+        rgba.x
+    }
+
+    fn border(argb: u32) -> Vec4 {
+        Vec4::new(argb_to_rgba(argb).x, 0.0, 0.0, 1.0)
+    }
+}
+
+/// Linear RGBA color. The sRGB encoded border color is decoded.
+impl Texel for Vec4 {
+    fn to_vec4(self) -> Vec4 {
+        self
+    }
+
+    fn from_vec4(rgba: Vec4) -> Self {
+        rgba
+    }
+
+    fn border(argb: u32) -> Vec4 {
+        let rgba = argb_to_rgba(argb);
+        srgb_to_linear(rgba.truncate()).extend(rgba.w)
+    }
+}
+
+/// How the color channels of 8-bit texels are encoded. Alpha is always linear.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ColorSpace {
+    /// Colors, such as base color and emissive textures.
+    Srgb,
+    /// Data, such as normals, roughness and occlusion.
+    Linear,
+}
+
+/// Texel data of one level of a texture. Texels are stored at `y * width + x`.
+pub struct MipLevel<T = u32> {
+    pub width: usize,
+    pub height: usize,
+    pub data: Vec<T>,
+}
+
+impl<T: Texel> MipLevel<T> {
+    /// Box filters texels down to half their size, rounding odd sizes down.
+    fn downsample(width: usize, height: usize, texels: &[T]) -> Self {
+        let mut level = Self {
+            width: (width / 2).max(1),
+            height: (height / 2).max(1),
+            data: Vec::new(),
+        };
+        for y in 0..level.height {
+            for x in 0..level.width {
+                let (x0, y0) = ((x * 2).min(width - 1), (y * 2).min(height - 1));
+                let (x1, y1) = ((x0 + 1).min(width - 1), (y0 + 1).min(height - 1));
+                let sum = [(x0, y0), (x1, y0), (x0, y1), (x1, y1)]
+                    .iter()
+                    .map(|&(x, y)| texels[y * width + x].to_vec4())
+                    .sum::<Vec4>();
+                level.data.push(T::from_vec4(sum * 0.25));
+            }
+        }
+        level
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Filter {
+    /// Closest texel of the full resolution level.
+    Nearest,
+    /// Four texels blended, from the closest mip level.
+    Bilinear,
+    /// Bilinear samples of the two closest mip levels, blended.
+    Trilinear,
+}
+
+/// How texture coordinates outside of 0 to 1 are mapped onto the texture.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum WrapMode {
+    /// The texture tiles.
+    Repeat,
+    /// The texture tiles, every other tile mirrored.
+    MirroredRepeat,
+    /// Coordinates are clamped to the edge texels.
+    ClampToEdge,
+    /// Texels outside of the texture take the sampler's border color.
+    ClampToBorder,
+}
+
+impl WrapMode {
+    /// Maps a texel coordinate on an axis of `size` texels, `None` for the border.
+    fn wrap(self, coord: i64, size: usize) -> Option<usize> {
+        let size = size as i64;
+        let coord = match self {
+            WrapMode::Repeat => coord.rem_euclid(size),
+            WrapMode::MirroredRepeat => {
+                let coord = coord.rem_euclid(2 * size);
+                if coord < size {
+                    coord
+                } else {
+                    2 * size - 1 - coord
+                }
+            }
+            WrapMode::ClampToEdge => coord.clamp(0, size - 1),
+            WrapMode::ClampToBorder => {
+                if !(0..size).contains(&coord) {
+                    return None;
+                }
+                coord
+            }
+        };
+        Some(coord as usize)
+    }
+}
+
+/// Describes how `TextureView::sample` filters and addresses a texture.
+#[derive(Debug, Copy, Clone)]
+pub struct Sampler {
+    pub filter: Filter,
+    /// Wrap mode along the texture's width.
+    pub wrap_u: WrapMode,
+    /// Wrap mode along the texture's height.
+    pub wrap_v: WrapMode,
+    /// ARGB color of texels outside the texture with `WrapMode::ClampToBorder`.
+    pub border_color: u32,
+    /// Most filtered taps taken along the pixel footprint, from 1 (isotropic) to 16. Ignored
+    /// with `Filter::Nearest`.
+    pub max_anisotropy: u32,
+}
+
+impl Sampler {
+    /// Default filtering with the same wrap mode on both axes.
+    pub fn with_wrap(wrap: WrapMode) -> Self {
+        Self {
+            wrap_u: wrap,
+            wrap_v: wrap,
+            ..Default::default()
+        }
+    }
+}
+
+impl Default for Sampler {
+    fn default() -> Self {
+        Self {
+            filter: Filter::Trilinear,
+            wrap_u: WrapMode::Repeat,
+            wrap_v: WrapMode::Repeat,
+            border_color: 0,
+            max_anisotropy: 1,
+        }
+    }
+}
+
+/// Texels of an image and its mip chain, stored row by row at `y * width + x` like the image's
+/// pixels. Images are loaded as 8-bit ARGB texels, which `to_linear` converts to linear RGBA
+/// for shading in linear light.
+pub struct Texture<T = u32> {
     pub width: usize,
     pub height: usize,
-    pub data: Vec<u32>,
+    pub data: Vec<T>,
     pub depth: usize,
+    /// Mip chain below the full resolution level, down to 1x1.
+    pub mips: Vec<MipLevel<T>>,
 }
 
 impl Texture {
+    /// Loads an image file with pixel (x, y) as texel (x, y), like `from_rgba8`.
     pub fn load(path: &Path) -> Result<Self, &'static str> {
-        let decoded_image = stb_image::image::load(path);
-        if let stb_image::image::LoadResult::ImageU8(image) = decoded_image {
-            let data;
-            if image.depth == 3 {
-                data = (0..image.data.len() / 3)
-                    .map(|id| {
-                        to_argb8(
-                            255,
-                            image.data[id * 3],
-                            image.data[id * 3 + 1],
-                            image.data[id * 3 + 2],
-                        )
-                    })
-                    .collect();
-            } else if image.depth == 4 {
-                data = (0..image.data.len() / 4)
-                    .map(|id| {
-                        to_argb8(
-                            image.data[id * 4 + 3],
-                            image.data[id * 4],
-                            image.data[id * 4 + 1],
-                            image.data[id * 4 + 2],
-                        )
-                    })
-                    .collect();
-            } else {
-                return Err("Unsupported texture type");
-            }
-            Ok(Self {
-                width: image.width,
-                height: image.height,
-                data,
-                depth: image.depth,
+        let stb_image::image::LoadResult::ImageU8(image) = stb_image::image::load(path) else {
+            return Err("Unsupported texture type");
+        };
+        let rgba: Vec<u8> = match image.depth {
+            1 => image.data.iter().flat_map(|&l| [l, l, l, 255]).collect(),
+            2 => image
+                .data
+                .chunks_exact(2)
+                .flat_map(|p| [p[0], p[0], p[0], p[1]])
+                .collect(),
+            3 => image
+                .data
+                .chunks_exact(3)
+                .flat_map(|p| [p[0], p[1], p[2], 255])
+                .collect(),
+            4 => image.data,
+            _ => return Err("Unsupported texture type"),
+        };
+        Ok(Self::from_rgba8(image.width, image.height, &rgba))
+    }
+
+    /// Creates a texture from rows of RGBA pixels, as images store them. Pixel (x, y) of the
+    /// image becomes texel (x, y), so u runs along the image's rows.
+    pub fn from_rgba8(width: usize, height: usize, rgba: &[u8]) -> Self {
+        let data = rgba[..width * height * 4]
+            .chunks_exact(4)
+            .map(|pixel| to_argb8(pixel[3], pixel[0], pixel[1], pixel[2]))
+            .collect();
+        let mut texture = Self {
+            width,
+            height,
+            data,
+            depth: 4,
+            mips: Vec::new(),
+        };
+        texture.generate_mips();
+        texture
+    }
+
+    /// Converts the texels to linear RGBA in 0 to 1, decoding the color channels if they are
+    /// sRGB encoded. The mip chain is rebuilt, so it is filtered in linear light.
+    pub fn to_linear(&self, color_space: ColorSpace) -> Texture<Vec4> {
+        let decode: Vec<f32> = (0..256)
+            .map(|c| match color_space {
+                ColorSpace::Srgb => srgb_to_linear(Vec3::splat(c as f32 / 255.0)).x,
+                ColorSpace::Linear => c as f32 / 255.0,
             })
-        } else {
-            Err("Unsupported texture type")
+            .collect();
+        let data = self
+            .data
+            .iter()
+            .map(|&argb| {
+                Vec4::new(
+                    decode[((argb >> 16) & 0xff) as usize],
+                    decode[((argb >> 8) & 0xff) as usize],
+                    decode[(argb & 0xff) as usize],
+                    (argb >> 24) as f32 / 255.0,
+                )
+            })
+            .collect();
+
+        let mut texture = Texture {
+            width: self.width,
+            height: self.height,
+            data,
+            depth: self.depth,
+            mips: Vec::new(),
+        };
+        texture.generate_mips();
+        texture
+    }
+
+    /// Closest texel at `uv`, texture coordinates repeat.
+    pub fn argb_at_uv(&self, u: f32, v: f32) -> u32 {
+        let sampler = Sampler {
+            filter: Filter::Nearest,
+            ..Default::default()
+        };
+        self.sample(&sampler, Vec2::new(u, v), Vec2::ZERO, Vec2::ZERO)
+    }
+}
+
+impl<T: Texel> Texture<T> {
+    /// Rebuilds the mip chain from the full resolution level.
+    pub fn generate_mips(&mut self) {
+        self.mips.clear();
+        loop {
+            let view = self.view();
+            let (width, height, texels) = view.level(self.mips.len());
+            if width <= 1 && height <= 1 {
+                break;
+            }
+            let mip = MipLevel::downsample(width, height, texels);
+            self.mips.push(mip);
         }
     }
 
-    fn coords_to_index(coord: Vec2, height: usize) -> usize {
-        coord.x as usize * height + coord.y as usize
+    /// Borrows the texels and mip chain, to be sampled or bound to a shader.
+    pub fn view(&self) -> TextureView<'_, T> {
+        TextureView {
+            width: self.width,
+            height: self.height,
+            data: &self.data,
+            mips: &self.mips,
+        }
     }
 
-    pub fn argb_at_uv(&self, u: f32, v: f32) -> u32 {
-        let u = u.abs() % 1.0;
-        let v = v.abs() % 1.0;
+    /// See `TextureView::sample`.
+    pub fn sample(&self, sampler: &Sampler, uv: Vec2, ddx: Vec2, ddy: Vec2) -> T {
+        self.view().sample(sampler, uv, ddx, ddy)
+    }
+}
 
-        let uv = Vec2::new(u * self.width as f32, v * self.height as f32);
-        let uv = uv.round();
+/// Borrowed texels of a texture or of a framebuffer attachment, laid out like `Texture`.
+/// Shaders sample views, so anything rendered earlier can be bound without copying it.
+#[derive(Copy, Clone)]
+pub struct TextureView<'a, T = u32> {
+    pub width: usize,
+    pub height: usize,
+    pub data: &'a [T],
+    /// Mip chain below the full resolution level, empty for render targets.
+    pub mips: &'a [MipLevel<T>],
+}
 
-        let id = Self::coords_to_index(uv, self.height);
-        if id < self.data.len() {
-            self.data[id]
-        } else {
-            to_argb8(255, 255, 0, 255)
+impl<'a, T: Texel> TextureView<'a, T> {
+    /// Texel of the full resolution level at integer coordinates, clamped to the edge.
+    pub fn texel(&self, x: i64, y: i64) -> T {
+        let x = x.clamp(0, self.width as i64 - 1) as usize;
+        let y = y.clamp(0, self.height as i64 - 1) as usize;
+        self.data[y * self.width + x]
+    }
+
+    /// Width, height and texels of a mip level, level 0 being the full resolution.
+    fn level(&self, level: usize) -> (usize, usize, &'a [T]) {
+        match level {
+            0 => (self.width, self.height, self.data),
+            _ => {
+                let mip = &self.mips[level - 1];
+                (mip.width, mip.height, &mip.data)
+            }
+        }
+    }
+
+    fn nearest(&self, sampler: &Sampler, level: usize, uv: Vec2) -> Vec4 {
+        let (width, height, data) = self.level(level);
+        let p = (uv * Vec2::new(width as f32, height as f32)).floor();
+        match (
+            sampler.wrap_u.wrap(p.x as i64, width),
+            sampler.wrap_v.wrap(p.y as i64, height),
+        ) {
+            (Some(x), Some(y)) => data[y * width + x].to_vec4(),
+            _ => T::border(sampler.border_color),
+        }
+    }
+
+    fn bilinear(&self, sampler: &Sampler, level: usize, uv: Vec2) -> Vec4 {
+        let (width, height, data) = self.level(level);
+        // Texel centers lie at half texel offsets
+        let p = uv * Vec2::new(width as f32, height as f32) - 0.5;
+        let p0 = p.floor();
+        let t = p - p0;
+
+        let (x, y) = (p0.x as i64, p0.y as i64);
+        let xs = [
+            sampler.wrap_u.wrap(x, width),
+            sampler.wrap_u.wrap(x + 1, width),
+        ];
+        let ys = [
+            sampler.wrap_v.wrap(y, height),
+            sampler.wrap_v.wrap(y + 1, height),
+        ];
+        let texel = |x: Option<usize>, y: Option<usize>| match (x, y) {
+            (Some(x), Some(y)) => data[y * width + x].to_vec4(),
+            _ => T::border(sampler.border_color),
+        };
+
+        let top = texel(xs[0], ys[0]).lerp(texel(xs[1], ys[0]), t.x);
+        let bottom = texel(xs[0], ys[1]).lerp(texel(xs[1], ys[1]), t.x);
+        top.lerp(bottom, t.y)
+    }
+
+    /// Bilinear or trilinear sample at `uv`, from the level of detail `lod`.
+    fn filtered(&self, sampler: &Sampler, uv: Vec2, lod: f32) -> Vec4 {
+        let lod = lod.clamp(0.0, self.mips.len() as f32);
+        match sampler.filter {
+            Filter::Trilinear => {
+                let level = lod.floor() as usize;
+                let next = (level + 1).min(self.mips.len());
+                self.bilinear(sampler, level, uv)
+                    .lerp(self.bilinear(sampler, next, uv), lod - level as f32)
+            }
+            _ => self.bilinear(sampler, lod.round() as usize, uv),
         }
     }
+
+    /// Samples the texture at `uv`, using the screen-space derivatives of `uv` to select the
+    /// mip level.
+    ///
+    /// With anisotropic filtering, the pixel footprint is covered by taps along its major axis
+    /// and the mip level is selected for the width of a single tap rather than the whole
+    /// footprint.
+    pub fn sample(&self, sampler: &Sampler, uv: Vec2, ddx: Vec2, ddy: Vec2) -> T {
+        if sampler.filter == Filter::Nearest {
+            return T::from_vec4(self.nearest(sampler, 0, uv));
+        }
+
+        // Footprint axes in texels
+        let size = Vec2::new(self.width as f32, self.height as f32);
+        let (dx, dy) = ((ddx * size).length(), (ddy * size).length());
+        let (major_axis, major, minor) = if dx >= dy {
+            (ddx, dx, dy)
+        } else {
+            (ddy, dy, dx)
+        };
+
+        let max_anisotropy = sampler.max_anisotropy.clamp(1, 16) as f32;
+        let taps = (major / minor.max(f32::MIN_POSITIVE))
+            .ceil()
+            .clamp(1.0, max_anisotropy) as usize;
+        let lod = (major / taps as f32).log2();
+
+        let color = if taps == 1 {
+            self.filtered(sampler, uv, lod)
+        } else {
+            (0..taps)
+                .map(|tap| {
+                    let offset = (tap as f32 + 0.5) / taps as f32 - 0.5;
+                    self.filtered(sampler, uv + major_axis * offset, lod)
+                })
+                .sum::<Vec4>()
+                / taps as f32
+        };
+        T::from_vec4(color)
+    }
+}
+
+/// Unpacks an ARGB color to RGBA in 0 to 1.
+fn argb_to_rgba(argb: u32) -> Vec4 {
+    Vec4::new(
+        ((argb >> 16) & 0xff) as f32,
+        ((argb >> 8) & 0xff) as f32,
+        (argb & 0xff) as f32,
+        (argb >> 24) as f32,
+    ) / 255.0
+}
+
+/// Packs RGBA to an ARGB color, clamping the channels to 0 to 1.
+fn rgba_to_argb(rgba: Vec4) -> u32 {
+    let c = (rgba.clamp(Vec4::ZERO, Vec4::ONE) * 255.0).round();
+    to_argb8(c.w as u8, c.x as u8, c.y as u8, c.z as u8)
 }
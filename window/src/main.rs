@@ -1,45 +1,24 @@
 extern crate minifb;
 
 use minifb::{Key, Window, WindowOptions};
-use shared::{camera::Camera, texture::Texture, transform::Transform, *};
-use std::{
-    path::Path,
-    time::{Instant, SystemTime},
-};
+use shared::{transform::Transform, *};
+use std::time::{Instant, SystemTime};
 
 pub mod reload;
 use crate::reload::*;
 
-static mut BUFFER: [u32; WIDTH * HEIGHT] = [0; WIDTH * HEIGHT];
-
-pub fn test_draw(x: u16, y: u16, color: u32) {
-    unsafe {
-        let index = x as usize * WIDTH + y as usize;
-        if index < WIDTH * HEIGHT {
-            BUFFER[index] = color;
-        }
-    }
-}
+/// Initial window size, the window can be resized at runtime.
+const WIDTH: usize = 600;
+const HEIGHT: usize = 600;
 
 fn main() {
-    let mut shared_state = State {
-        version: 1,
-        time_passed: 0.0,
-        draw_fn: test_draw,
-        meshes: Vec::new(),
-        textures: Vec::new(),
-        camera: Camera {
-            fov: WIDTH as f32 / HEIGHT as f32,
-            transform: Transform::from_translation(glam::vec3(0.0, 1.5, 6.0)),
-            ..Default::default()
-        },
-        should_clear: true,
-        clear_color: 0x00,
-    };
+    let mut shared_state = State::new(WIDTH, HEIGHT);
+    shared_state.camera.fov = 1.0;
+    shared_state.camera.transform = Transform::from_translation(glam::vec3(0.0, 1.5, 6.0));
 
     let mut app: Application;
     app = load_lib();
-    app.setup(&shared_state);
+    app.setup(&mut shared_state);
 
     let mut last_modified = SystemTime::now();
 
@@ -47,7 +26,10 @@ fn main() {
         "Test - ESC to exit",
         WIDTH,
         HEIGHT,
-        WindowOptions::default(),
+        WindowOptions {
+            resize: true,
+            ..WindowOptions::default()
+        },
     )
     .unwrap_or_else(|e| {
         panic!("{}", e);
@@ -62,11 +44,13 @@ fn main() {
         let start_time = Instant::now();
         if should_reload(last_modified) {
             println!("== NEW VERSION FOUND ==");
+            // The renderer may refer to the old library's code
+            shared_state.renderer = None;
             app = reload(app);
             println!("== NEW VERSION LOADED ==");
             shared_state.version += 1;
             last_modified = SystemTime::now();
-            app.setup(&shared_state);
+            app.setup(&mut shared_state);
         }
 
         // Handle input
@@ -90,33 +74,53 @@ fn main() {
                     shared_state
                         .camera
                         .transform
-                        .translate(shared_state.camera.transform.up() * 100.0 * dt);
+                        .translate(-shared_state.camera.transform.right() * 100.0 * dt);
                 }
                 Key::D => {
                     shared_state
                         .camera
                         .transform
-                        .translate(-shared_state.camera.transform.up() * 100.0 * dt);
+                        .translate(shared_state.camera.transform.right() * 100.0 * dt);
                 }
                 _ => (),
             });
+        // Cycle through the MSAA sample counts
+        if window.is_key_pressed(Key::M, minifb::KeyRepeat::No) {
+            let samples = match shared_state.framebuffer.samples {
+                1 => 2,
+                2 => 4,
+                4 => 8,
+                _ => 1,
+            };
+            println!("MSAA: {}x", samples);
+            shared_state.set_samples(samples);
+        }
+
+        // Reallocate the framebuffer when the window was resized
+        let (width, height) = window.get_size();
+        let framebuffer = &shared_state.framebuffer;
+        if width > 0 && height > 0 && (framebuffer.width, framebuffer.height) != (width, height) {
+            shared_state.resize(width, height);
+        }
+
         // Clear screen if required
         if shared_state.should_clear {
-            unsafe {
-                for i in 0..BUFFER.len() {
-                    BUFFER[i] = shared_state.clear_color;
-                }
-            }
+            shared_state
+                .framebuffer
+                .clear_color(shared_state.clear_color);
         }
+        shared_state.framebuffer.clear_depth();
 
-        app.update(&shared_state);
+        app.update(&mut shared_state);
         let elapsed_time = start_time.elapsed();
         println!("Frame render time: {} ms", elapsed_time.as_millis());
 
-        unsafe {
-            // We unwrap here as we want this code to exit if it fails. Real applications may want to handle this in a different way
-            window.update_with_buffer(&BUFFER, WIDTH, HEIGHT).unwrap();
-        }
+        let framebuffer = &shared_state.framebuffer;
+        let resolved = framebuffer.resolve();
+        // We unwrap here as we want this code to exit if it fails. Real applications may want to handle this in a different way
+        window
+            .update_with_buffer(&resolved, framebuffer.width, framebuffer.height)
+            .unwrap();
 
         let elapsed_time = start_time.elapsed();
         println!(
@@ -127,4 +131,7 @@ fn main() {
         dt = elapsed_time.as_secs_f32();
         shared_state.time_passed += elapsed_time.as_secs_f32();
     }
+
+    // The library is unloaded before the state would drop the renderer
+    shared_state.renderer = None;
 }
@@ -18,15 +18,15 @@ impl Application {
             f()
         }
     }
-    pub fn setup(&self, test: &State) {
+    pub fn setup(&self, test: &mut State) {
         unsafe {
-            let f = self.0.get::<fn(&State)>(b"setup\0").unwrap();
+            let f = self.0.get::<fn(&mut State)>(b"setup\0").unwrap();
             f(test)
         }
     }
-    pub fn update(&self, test: &State) {
+    pub fn update(&self, test: &mut State) {
         unsafe {
-            let f = self.0.get::<fn(&State)>(b"update\0").unwrap();
+            let f = self.0.get::<fn(&mut State)>(b"update\0").unwrap();
             f(test)
         }
     }